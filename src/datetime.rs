@@ -0,0 +1,134 @@
+use crate::parser::{
+    *,
+    core::*,
+};
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DateTime {
+    pub date: (i32, u32, u32),
+    pub time: (u32, u32, u32),
+    // Minutes east of UTC, `None` if no offset was given
+    pub offset: Option<i32>,
+}
+
+
+// Number made of exactly `n` digits
+fn fixed<'a> (n: usize) -> BoxedParser<'a, u32> {
+    BoxedParser::new(count(digit(), n))
+        .map(|v| v.into_iter().fold(0, |acc, d| acc * 10 + d))
+}
+
+// Number made of exactly `n` digits, within `lo..=hi`
+fn ranged<'a> (n: usize, lo: u32, hi: u32) -> BoxedParser<'a, u32> {
+    let p = verify(fixed(n), move |v| (lo..=hi).contains(v));
+
+    // Components are never separated by whitespace
+    BoxedParser::new(raw(move |buf: &str| {
+        if buf.starts_with(char::is_whitespace) {
+            return par_err(buf, "expected digit");
+        }
+        p.parse(buf)
+    }))
+}
+
+
+/// ISO-8601 calendar date, `YYYY-MM-DD`, as (year, month, day)
+pub fn parse_date<'a> () -> BoxedParser<'a, (i32, u32, u32)> {
+    BoxedParser::new(fixed(4))
+        .and(prefix_raw("-", ranged(2, 1, 12)))
+        .and(prefix_raw("-", ranged(2, 1, 31)))
+        .map(|((y, m), d)| (y as i32, m, d))
+}
+
+
+// `hh:mm:ss`, allowing a leap second
+fn parse_time<'a> () -> BoxedParser<'a, (u32, u32, u32)> {
+    BoxedParser::new(ranged(2, 0, 23))
+        .and(prefix_raw(":", ranged(2, 0, 59)))
+        .and(prefix_raw(":", ranged(2, 0, 60)))
+        .map(|((h, m), s)| (h, m, s))
+}
+
+
+// `Z`, or `+hh:mm` / `-hh:mm`, in minutes east of UTC
+fn parse_offset<'a> () -> BoxedParser<'a, i32> {
+    let hhmm = signed(BoxedParser::new(ranged(2, 0, 23))
+        .and(prefix_raw(":", ranged(2, 0, 59)))
+        .map(|(h, m)| (h * 60 + m) as i32));
+    BoxedParser::new(raw(move |buf: &str| match buf.chars().next() {
+        Some('Z')       => Ok((&buf[1..], 0)),
+        Some('+' | '-') => hhmm.parse(buf),
+        _ => par_err(buf, "expected 'Z' or offset")
+    }))
+}
+
+
+/// ISO-8601 date and time, `YYYY-MM-DDThh:mm:ss`, with an optional `Z` or
+/// `+hh:mm` offset
+pub fn parse_datetime<'a> () -> BoxedParser<'a, DateTime> {
+    parse_date()
+        .and(prefix_raw("T", parse_time()))
+        .and(raw_option(parse_offset()))
+        .map(|((date, time), offset)| DateTime { date, time, offset })
+}
+
+
+// `prefix`, without skipping whitespace around the literal
+fn prefix_raw<'a, T: 'a> (lit: &str, p: BoxedParser<'a, T>) -> BoxedParser<'a, T> {
+    let lit = raw_literal(lit);
+    BoxedParser::new(raw(move |buf: &str| {
+        let (buf, _) = lit.parse(buf)?;
+        if buf.starts_with(char::is_whitespace) {
+            return par_err(buf, "unexpected whitespace");
+        }
+        p.parse(buf)
+    }))
+}
+
+// `option`, without skipping whitespace
+fn raw_option<'a, T: 'a> (p: BoxedParser<'a, T>) -> BoxedParser<'a, Option<T>> {
+    BoxedParser::new(raw(move |buf: &str| match p.parse(buf) {
+        Ok((buf, o)) => Ok((buf, Some(o))),
+        Err(_)       => Ok((buf, None)),
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date () {
+        assert!(parse_date().parse("2023-02-28").unwrap() == ("", (2023, 2, 28)));
+        assert!(parse_date().parse("1999-12-31 rest").unwrap() == (" rest", (1999, 12, 31)));
+
+        assert!(parse_date().parse("2023-13-01").is_err());
+        assert!(parse_date().parse("2023-00-01").is_err());
+        assert!(parse_date().parse("2023-01-32").is_err());
+        assert!(parse_date().parse("2023-1-01").is_err());
+        assert!(parse_date().parse("2023 -01-01").is_err());
+        assert!(parse_date().parse("2023- 01-01").is_err());
+    }
+
+    #[test]
+    fn test_datetime () {
+        let dt = parse_datetime().test("2023-06-01T12:30:05+05:30");
+        assert!(dt == DateTime { date: (2023, 6, 1), time: (12, 30, 5), offset: Some(330) });
+
+        let dt = parse_datetime().test("2023-06-01T23:59:60Z");
+        assert!(dt.time == (23, 59, 60) && dt.offset == Some(0));
+
+        let (buf, dt) = parse_datetime().parse("2023-06-01T00:00:00 -01:00").unwrap();
+        assert!(dt.offset.is_none() && buf == " -01:00");
+
+        let dt = parse_datetime().test("2023-06-01T00:00:00-01:00");
+        assert!(dt.offset == Some(-60));
+
+        assert!(parse_datetime().parse("2023-06-01T24:00:00").is_err());
+        assert!(parse_datetime().parse("2023-06-01 12:00:00").is_err());
+        assert!(parse_datetime().parse("2023-06-01T 12:00:00").is_err());
+        assert!(parse_datetime().parse("2023-06-01T12:00:00+ 01:00").unwrap().1.offset.is_none());
+    }
+}
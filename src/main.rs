@@ -6,6 +6,7 @@ use std::io::{self, BufRead, Write};
 mod ast;
 mod parser;
 mod interpreter;
+mod datetime;
 
 use parser::*;
 use interpreter::Environment;
@@ -7,6 +7,8 @@ pub mod conditional;
 pub mod operation;
 pub mod print;
 pub mod _loop;
+pub mod formats;
+pub mod state;
 
 use std::boxed::Box;
 
@@ -15,21 +17,97 @@ use self::core::*;
 const BLD: &str = "\x1b[1m";
 const RST: &str = "\x1b[0m";
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ParseErr<'a> {
     msg: String,
     ptr: &'a str,
+    // Fatal errors are not backtracked out of, see `core::cut`
+    fatal: bool,
+    // What would have been accepted here, eg. "')'"
+    expected: Vec<String>,
 }
 impl<'a> ParseErr<'a> {
     pub fn new (s: String, ptr: &'a str) -> Self {
-        Self { msg: s, ptr }
+        Self { msg: s, ptr, fatal: false, expected: vec![] }
+    }
+
+    pub fn expecting (mut self, label: String) -> Self {
+        self.expected.push(label);
+        self
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.fatal
+    }
+
+    // The same error, reported at `ptr` instead
+    pub fn with_ptr<'b> (self, ptr: &'b str) -> ParseErr<'b> {
+        ParseErr { msg: self.msg, ptr, fatal: self.fatal, expected: self.expected }
+    }
+
+    /// Combines the errors of alternatives tried at the same position. Only the
+    /// error(s) that got furthest are kept, along with their expected labels.
+    pub fn merge (self, other: ParseErr<'a>) -> ParseErr<'a> {
+        // Less input remaining means the parser got further
+        match self.ptr.len().cmp(&other.ptr.len()) {
+            std::cmp::Ordering::Less    => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal   => {
+                let mut err = other;
+                for label in self.expected.into_iter().rev() {
+                    err.expected.insert(0, label);
+                }
+                err
+            }
+        }
+    }
+
+    // Expected labels, deduplicated and in the order they were first reported
+    pub fn expected_labels (&self) -> Vec<&str> {
+        let mut labels: Vec<&str> = vec![];
+        for label in self.expected.iter() {
+            if !labels.contains(&label.as_str()) {
+                labels.push(label);
+            }
+        }
+        labels
+    }
+
+    // Byte offset of the error within `buf`, the input it was parsed from. An
+    // error pointing outside of `buf` is clamped to its start or end.
+    pub fn offset(&self, buf: &str) -> usize {
+        let at = (self.ptr.as_ptr() as usize).saturating_sub(buf.as_ptr() as usize);
+        at.min(buf.len())
+    }
+
+    /// The token at the error in `original`: a word, or otherwise the single
+    /// character there. `None` at the end of the input.
+    pub fn found_token<'b> (&self, original: &'b str) -> Option<&'b str> {
+        let rest = original[self.offset(original)..].trim_start();
+        let c = rest.chars().next()?;
+        let len = if is_ident_char(c) {
+            rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len())
+        } else {
+            c.len_utf8()
+        };
+        Some(&rest[..len])
+    }
+
+    /// The message for display, saying what was found at the error, eg.
+    /// "expected ')', found 'then'". Uses the expected labels, if there are any.
+    pub fn message (&self, original: &str) -> String {
+        let labels = self.expected_labels();
+        let head = if labels.is_empty() { self.msg.clone() } else { format!("expected {}", labels.join(" or ")) };
+        match self.found_token(original) {
+            Some(tok) => format!("{}, found '{}'", head, tok),
+            None      => format!("{}, found end of input", head),
+        }
     }
 
     pub fn print(&self, buf: &str) {
 
         // Calculate index
-        let ptr = self.ptr.as_ptr() as usize;
-        let index = ptr - buf.as_ptr() as usize;
+        let index = self.offset(buf);
 
         // Get relevant line
         let (line, line_index) = {
@@ -39,7 +117,7 @@ impl<'a> ParseErr<'a> {
             while buf.get(r..=r).is_some() && buf.get(r..=r).unwrap() != "\n" { r += 1; }
             (&buf[l..r], index - l)
         };
-        println!("{BLD}[== Parser Err ==]{RST}  {}", self.msg);
+        println!("{BLD}[== Parser Err ==]{RST}  {}", self.message(buf));
         println!("--> at character #{BLD}{}{RST}", index);
         println!("|\n|\t{}", line);
         print!("|\t");
@@ -50,6 +128,130 @@ impl<'a> ParseErr<'a> {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SpannedError {
+    pub msg: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A parsed value with the byte offsets of the text it was parsed from
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Parses all of `input` with `p`, carrying on past failures to report every
+/// error, each spanning from where it occurred to the end of its line. After
+/// an error that line is blanked out and the document parsed again, so the
+/// value is that of the document without its erroneous lines, if it parses.
+pub fn parse_collecting<T, P> (p: P, input: &str) -> (Option<T>, Vec<SpannedError>)
+where
+    P: Parser<T>
+{
+    let mut errs = vec![];
+    let mut doc = input.to_owned();
+    loop {
+        let (start, msg) = match p.parse(&doc) {
+            Ok((rest, o)) if rest.trim().is_empty() => return (Some(o), errs),
+            Ok((rest, _)) => (doc.len() - rest.trim_start().len(), "unparsed trailing input".to_owned()),
+            Err(e) => (e.offset(&doc), e.msg),
+        };
+        let end = doc[start..].find(['\n', '\r']).map_or(doc.len(), |i| start + i);
+        errs.push(SpannedError { msg, start, end });
+
+        // Blanking keeps offsets, and ends the loop once nothing is left to blank
+        if doc[start..end].trim().is_empty() {
+            return (None, errs);
+        }
+        doc.replace_range(start..end, &" ".repeat(end - start));
+    }
+}
+
+/// Lazily parses `input` as a sequence of `p`, one item per `next`. Ends at the
+/// end of the input, or after yielding the first error.
+pub fn parse_iter<'a, T, P> (p: P, input: &'a str) -> impl Iterator<Item = Result<T, ParseErr<'a>>>
+where
+    P: Parser<T>
+{
+    let mut s = Some(input);
+    std::iter::from_fn(move || {
+        let buf = s?.trim_start();
+        if buf.is_empty() {
+            return None;
+        }
+        match p.parse(buf) {
+            // A match that consumes nothing would repeat forever
+            Ok((rest, _)) if rest.len() == buf.len() => {
+                s = None;
+                Some(Err(ParseErr::new("pattern matched without consuming input".to_owned(), buf)))
+            },
+            Ok((rest, o)) => {
+                s = Some(rest);
+                Some(Ok(o))
+            },
+            Err(e) => {
+                s = None;
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+/// Lexes the whole of `input` into `token`s, with their spans, running `skip`
+/// before each to pass over comments and the like. Whitespace is always skipped.
+pub fn tokenize<'a, T, P, S> (token: P, skip: S, input: &'a str) -> Result<Vec<Spanned<T>>, ParseErr<'a>>
+where
+    P: Parser<T>,
+    S: Parser<()>
+{
+    let mut tokens = vec![];
+    let mut buf = input;
+    loop {
+        buf = skip.parse(buf).map_or(buf, |(rest, _)| rest);
+        let start = skip_whitespace(buf);
+        if start.is_empty() {
+            return Ok(tokens);
+        }
+        let (rest, value) = token.parse(start)?;
+        // A match that consumes nothing would repeat forever
+        if rest.len() == start.len() {
+            return Err(ParseErr::new("token matched without consuming input".to_owned(), start));
+        }
+        tokens.push(Spanned { value, start: input.len() - start.len(), end: input.len() - rest.len() });
+        buf = rest;
+    }
+}
+
+/// Parses `input` with `p` for callers that may resume, eg. a REPL. Returns
+/// the value if it parsed, else the error, along with the offset it stopped
+/// at. A failure at the very end of `input` suggests it is incomplete rather
+/// than malformed.
+pub fn parse_partial<'a, T, P> (p: P, input: &'a str) -> (Option<T>, Option<ParseErr<'a>>, usize)
+where
+    P: Parser<T>
+{
+    match p.parse(input) {
+        Ok((rest, o)) => (Some(o), None, input.len() - rest.len()),
+        Err(e) => {
+            let at = e.offset(input);
+            (None, Some(e), at)
+        }
+    }
+}
+
+/// Parses a whole document with `p`, past any leading byte order mark. Line
+/// endings are left in place; match them with `line_ending`, which accepts all
+/// of "\n", "\r\n" and "\r", so error offsets stay those of `input`.
+pub fn parse_normalized<'a, T, P> (p: P, input: &'a str) -> ParseRes<'a, T>
+where
+    P: Parser<T>
+{
+    strip_bom(p).parse(input)
+}
+
 pub fn par_err_s<T> (ptr: &str, s: String) -> ParseRes<T> {
     Err( ParseErr::new(s, ptr) )
 }
@@ -66,21 +268,85 @@ where
     F: Fn (& str) -> ParseRes<T>
 {
     fn parse<'a> (&self, input: &'a str) -> ParseRes<'a, T> {
-        let start = {
-            let mut iter = input.chars();
-            let mut counter = 0;
-            while let Some(c) = iter.next() {
-                if c != ' ' && c != '\n' && c != '\r' {
-                    break;
-                }
-                counter += 1;
+        self(skip_whitespace(input))
+    }
+}
+
+// Strips the whitespace that parsers skip before matching
+pub fn skip_whitespace (input: &str) -> &str {
+    let start = {
+        let mut iter = input.chars();
+        let mut counter = 0;
+        while let Some(c) = iter.next() {
+            if c != ' ' && c != '\n' && c != '\r' {
+                break;
             }
-            counter
-        };
-        self(&input[start..])
+            counter += 1;
+        }
+        counter
+    };
+    &input[start..]
+}
+
+/// Parsers that can summarize what they accept, eg. "the literal ')'"
+pub trait DescribableParser {
+    fn describe (&self) -> String;
+}
+
+pub struct Described<P> {
+    parser: P,
+    desc: String,
+}
+impl<P, T> Parser<T> for Described<P> 
+where
+    P: Parser<T>
+{
+    fn parse<'a> (&self, input: &'a str) -> ParseRes<'a, T> {
+        self.parser.parse(input)
+    }
+}
+impl<P> DescribableParser for Described<P> {
+    fn describe (&self) -> String {
+        self.desc.clone()
+    }
+}
+
+// Parser that reads the buffer as-is, without skipping leading whitespace
+pub struct Raw<F> (F);
+impl<F, T> Parser<T> for Raw<F> 
+where
+    F: Fn (& str) -> ParseRes<T>
+{
+    fn parse<'a> (&self, input: &'a str) -> ParseRes<'a, T> {
+        (self.0)(input)
     }
 }
 
+// A tuple of parsers runs them in order, returning the tuple of their results
+macro_rules! tuple_parser {
+    ($($P:ident $T:ident),+) => {
+        impl<$($P, $T),+> Parser<($($T,)+)> for ($($P,)+)
+        where
+            $($P: Parser<$T>),+
+        {
+            #[allow(non_snake_case)]
+            fn parse<'a> (&self, input: &'a str) -> ParseRes<'a, ($($T,)+)> {
+                let ($($P,)+) = self;
+                let buf = input;
+                $(let (buf, $T) = $P.parse(buf)?;)+
+                Ok((buf, ($($T,)+)))
+            }
+        }
+    };
+}
+tuple_parser!(P1 T1, P2 T2);
+tuple_parser!(P1 T1, P2 T2, P3 T3);
+tuple_parser!(P1 T1, P2 T2, P3 T3, P4 T4);
+tuple_parser!(P1 T1, P2 T2, P3 T3, P4 T4, P5 T5);
+tuple_parser!(P1 T1, P2 T2, P3 T3, P4 T4, P5 T5, P6 T6);
+tuple_parser!(P1 T1, P2 T2, P3 T3, P4 T4, P5 T5, P6 T6, P7 T7);
+tuple_parser!(P1 T1, P2 T2, P3 T3, P4 T4, P5 T5, P6 T6, P7 T7, P8 T8);
+
 pub struct BoxedParser<'a, T> {
     parser: Box<dyn Parser<T> + 'a>
 }
@@ -115,6 +381,14 @@ where
         BoxedParser::new( map(self, f) ) 
     }
 
+    pub fn try_map<O, F> (self, f: F) -> BoxedParser<'a, O> 
+    where
+        O: 'a,
+        F: Fn(T) -> Result<O, String> + 'a
+    {
+        BoxedParser::new( try_map(self, f) ) 
+    }
+
     pub fn and<B, P> (self, p: P) -> BoxedParser<'a, (T, B)> 
     where
         B: 'a,
@@ -122,6 +396,14 @@ where
     {
         BoxedParser::new( and(self, p) ) 
     }
+    pub fn and_then<B, P, F> (self, f: F) -> BoxedParser<'a, B> 
+    where
+        B: 'a,
+        P: Parser<B> + 'a,
+        F: Fn(T) -> P + 'a
+    {
+        BoxedParser::new( and_then(self, f) ) 
+    }
     pub fn or<P> (self, p: P) -> BoxedParser<'a, T> 
     where
         P: Parser<T> +'a
@@ -154,10 +436,124 @@ impl<'b, T> Parser<T> for BoxedParser<'b, T> {
 
 #[cfg(test)]
 impl<'b, T> BoxedParser<'b, T> {
-    fn test<'a> (&self, buf: &'a str) -> T {
+    pub fn test<'a> (&self, buf: &'a str) -> T {
         self.parser.parse(buf).unwrap().1
     }
 }
 
 unsafe impl<'a, T> Send for BoxedParser<'a, T> {} 
 unsafe impl<'a, T> Sync for BoxedParser<'a, T> {} 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use declare::declaration;
+
+    #[test]
+    fn test_parse_normalized () {
+        let doc = "\u{FEFF}name = demo\r\nmode = fast\rsize = 3\r\n";
+        let (rest, map) = parse_normalized(key_values("=", line_ending()), doc).unwrap();
+        assert!(rest == "\r\n" && map.len() == 3);
+        assert!(map["name"] == "demo" && map["mode"] == "fast" && map["size"] == "3");
+
+        let err = parse_normalized(parse_literal("x"), doc).unwrap_err();
+        assert!(err.offset(doc) == 3);
+    }
+
+    #[test]
+    fn test_tuple_parser () {
+        let p = map((parse_identifier, parse_literal("="), parse_number), |(k, _, v)| (k, v));
+        assert!(p.parse("x = 1.5;") == Ok((";", ("x".to_owned(), 1.5))));
+        assert!(p.parse("x 1.5").is_err());
+
+        let pair = (parse_int, (parse_literal(","), parse_int));
+        assert!(pair.parse("1, 2") == Ok(("", (1, (",".to_owned(), 2)))));
+    }
+
+    #[test]
+    fn test_parse_iter () {
+        let lazy: Result<Vec<_>, _> = parse_iter(parse_int, "1 2 3 ").collect();
+        assert!(lazy.unwrap() == zero_or_more(parse_int).parse("1 2 3 ").unwrap().1);
+
+        let mut it = parse_iter(parse_int, "1 x 3");
+        assert!(it.next() == Some(Ok(1)));
+        assert!(it.next().is_some_and(|r| r.is_err()));
+        assert!(it.next().is_none());
+    }
+
+    #[test]
+    fn test_parse_partial () {
+        let (v, e, at) = parse_partial(arg_list(parse_int), "(1, 2) x");
+        assert!(v == Some(vec![1, 2]) && e.is_none() && at == 6);
+
+        // Incomplete: stops at the end of the input
+        let (v, e, at) = parse_partial(arg_list(parse_int), "(1, 2");
+        assert!(v.is_none() && e.is_some() && at == 5);
+
+        let (v, e, at) = parse_partial(arg_list(parse_int), "(1 2)");
+        assert!(v.is_none() && e.is_some() && at == 3);
+    }
+
+    #[test]
+    fn test_found_token () {
+        let input = "if (x then";
+        let err = prefix("if", surround("(", ")", parse_identifier)).parse(input).unwrap_err();
+        assert!(err.found_token(input) == Some("then"));
+        assert!(err.message(input) == "expected ')', found 'then'");
+
+        let input = "f(1; 2)";
+        let err = arg_list(parse_int).parse(&input[1..]).unwrap_err();
+        assert!(err.found_token(input) == Some(";"));
+
+        let input = "let x =";
+        let err = prefix("=", parse_number).parse(&input[6..]).unwrap_err();
+        assert!(err.found_token(input).is_none());
+        assert!(err.message(input).ends_with(", found end of input"));
+    }
+
+    #[test]
+    fn test_tokenize () {
+        #[derive(Debug, PartialEq, Clone)]
+        enum Tok { Num(i64), Op(String) }
+        let token = || or(map(parse_int, Tok::Num), map(raw_literal("+"), Tok::Op));
+        let comment = || skip_many(and(raw_literal("#"), field("\n")));
+
+        let tokens = tokenize(token(), comment(), "1 + 2").unwrap();
+        assert!(tokens == vec![
+            Spanned { value: Tok::Num(1), start: 0, end: 1 },
+            Spanned { value: Tok::Op("+".to_owned()), start: 2, end: 3 },
+            Spanned { value: Tok::Num(2), start: 4, end: 5 },
+        ]);
+
+        let tokens = tokenize(token(), comment(), "# one\n1 # two\n").unwrap();
+        assert!(tokens == vec![Spanned { value: Tok::Num(1), start: 6, end: 7 }]);
+        assert!(tokenize(token(), comment(), "1 - 2").unwrap_err().offset("1 - 2") == 2);
+    }
+
+    #[test]
+    fn test_parse_collecting () {
+        let input = "let a = 1\nlet = 2\nlet b = 3\nlet c = *\nlet d = 4";
+
+        let (items, errs) = parse_collecting(zero_or_more(declaration), input);
+        let idents: Vec<String> = items.unwrap().into_iter().map(|d| d.ident).collect();
+
+        assert!(idents == vec!["a", "b", "d"]);
+        assert!(errs.len() == 2);
+        assert!(&input[errs[0].start..errs[0].end] == "let = 2");
+        assert!(&input[errs[1].start..errs[1].end] == "let c = *");
+
+        // Without a value once the erroneous lines are gone
+        let (o, errs) = parse_collecting(parse_int, "x");
+        assert!(o.is_none() && errs.len() == 2 && errs[1].start == 1);
+        let (o, errs) = parse_collecting(parse_int, "1\n2");
+        assert!(o == Some(1) && errs.len() == 1 && errs[0].start == 2);
+
+        // An error pointing outside of the input is clamped into it
+        let (o, errs) = parse_collecting(bind(|_: &str| par_err::<()>("", "lost")), "x");
+        assert!(o.is_none() && errs.iter().all(|e| e.start <= 1 && e.end <= 1));
+        let other = String::from("elsewhere");
+        let err = ParseErr::new("lost".to_owned(), &other[4..]);
+        assert!(err.offset("ab") <= 2 && err.found_token("ab").is_none_or(|t| "ab".contains(t)));
+    }
+}
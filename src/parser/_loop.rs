@@ -8,7 +8,7 @@ use crate::ast::{
 use crate::interpreter::{InterpreterErr, Environment};
 use crate::parser::{
     *,
-    core::{parse_literal, surround},
+    core::{parse_literal, surround, commit_after, and},
 };
 
 
@@ -31,10 +31,8 @@ impl Operation for Loop {
 
 
 pub fn _while<'a> () -> BoxedParser<'a, Loop> {
-    BoxedParser::new(parse_literal("while "))
-        .and(Expr::bool)
-        .and(block::block)
-        .map(|((_, expr), block)| Loop{ expr, block })
+    BoxedParser::new(commit_after(parse_literal("while "), and(Expr::bool, block::block)))
+        .map(|(_, (expr, block))| Loop{ expr, block })
 }
 
 
@@ -102,6 +100,16 @@ mod test {
         */
     }
 
+    #[test]
+    fn test_while_commits () {
+        // The malformed body is reported, rather than trying other statements
+        let input = "while true { x = }";
+        match operation(input) {
+            Err(e) => assert!(e.is_fatal() && e.ptr == "x = }"),
+            Ok(_)  => panic!("malformed loop body parsed"),
+        }
+    }
+
     #[test]
     fn test_for () {
         /* Cannot be used until equality is implemented
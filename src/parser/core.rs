@@ -1,3 +1,9 @@
+use std::borrow::Cow;
+use std::rc::Rc;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::ops::Neg;
+
 use super::*;
 
 
@@ -7,6 +13,21 @@ where
 {
     f
 }
+pub fn raw<F, T>(f: F) -> Raw<F>
+where
+    F: for<'a> Fn(&'a str) -> ParseRes<'a, T>,
+{
+    Raw(f)
+}
+/// Pins a closure to the `Parser` signature, for closures stored in a `let`
+/// before use, whose argument lifetime would otherwise be inferred too narrowly:
+/// `let p = apply(|buf: &str| parse_number(buf));`
+pub fn apply<F, T>(f: F) -> impl Parser<T>
+where
+    F: for<'a> Fn(&'a str) -> ParseRes<'a, T>,
+{
+    bind(f)
+}
 fn bind2<'t, F, T>(f: F) -> F
 where
     F: Fn(&'t str) -> ParseRes<'t, T>,
@@ -19,14 +40,45 @@ where
     P: Parser<T>
 {
     bind(move |buf: & str| {
-        if let Ok((buf, o)) = p.parse(buf) {
-            Ok((buf, Some(o)))
-        } else {
-            Ok((buf, None))
+        match p.parse(buf) {
+            Ok((buf, o))            => Ok((buf, Some(o))),
+            Err(e) if e.is_fatal()  => Err(e),
+            Err(_)                  => Ok((buf, None)),
         }
     })
 }
 
+/// `p`, or on failure the value `f` makes from the error, consuming nothing.
+/// Useful to stand in an error node for a malformed piece of input. Fatal
+/// errors are passed on, as after a `cut`.
+pub fn or_else_with<T, P, F> (p: P, f: F) -> impl Parser<T>
+where
+    P: Parser<T>,
+    F: Fn(&ParseErr) -> T
+{
+    bind(move |buf: &str| match p.parse(buf) {
+        Ok(r)                  => Ok(r),
+        Err(e) if e.is_fatal() => Err(e),
+        Err(e)                 => Ok((buf, f(&e))),
+    })
+}
+
+/// Like `option`, with the offset at which `p` was tried, so an omission can
+/// be given a position. Offsets are into the `with_origin` input, so this must
+/// run within one; it fails otherwise, as there is nothing to measure from.
+pub fn option_spanned<T, P> (p: P) -> impl Parser<(Option<T>, usize)>
+where
+    P: Parser<T>
+{
+    let p = option(p);
+    bind(move |buf: &str| {
+        let Some(at) = origin_offset(buf) else {
+            return par_err(buf, "option_spanned used outside of with_origin");
+        };
+        p.parse(buf).map(|(rest, o)| (rest, (o, at)))
+    })
+}
+
 pub fn and<'a, A, B, PA, PB> (a: PA, b: PB) -> impl Parser<(A, B)> 
 where
     PA: Parser<A>,
@@ -41,57 +93,567 @@ where
     })
 }
 
-pub fn or<'a, P1, P2, A>(parser1: P1, parser2: P2) -> impl Parser<A>
+pub struct Or<P1, P2> {
+    parser1: P1,
+    parser2: P2,
+}
+impl<P1, P2, A> Parser<A> for Or<P1, P2>
 where
     P1: Parser<A>,
     P2: Parser<A>,
 {
-    bind(move |input| match parser1.parse(input) {
-        ok @ Ok(_) => ok,
-        Err(e) => {
-            println!("option one failed with: {:?}", e);
-            parser2.parse(input)
-        },
+    fn parse<'a> (&self, input: &'a str) -> ParseRes<'a, A> {
+        let input = skip_whitespace(input);
+        match self.parser1.parse(input) {
+            ok @ Ok(_) => ok,
+            Err(e) if e.is_fatal() => Err(e),
+            Err(e) => {
+                self.parser2.parse(input).map_err(|e2| if e2.is_fatal() { e2 } else { e.merge(e2) })
+            },
+        }
+    }
+}
+impl<P1, P2> DescribableParser for Or<P1, P2>
+where
+    P1: DescribableParser,
+    P2: DescribableParser,
+{
+    // Nested alternatives are flattened into a single list
+    fn describe (&self) -> String {
+        let a = self.parser1.describe();
+        let b = self.parser2.describe();
+        let a = a.strip_prefix("one of: ").unwrap_or(&a);
+        let b = b.strip_prefix("one of: ").unwrap_or(&b);
+        format!("one of: {}, {}", a, b)
+    }
+}
+
+/// `first`, `between` and `second`, where `first` and `second` must parse to
+/// equal values, eg. matching open and close tags. Returns that value.
+pub fn same<T, P1, PB, P2> (first: P1, between: PB, second: P2) -> impl Parser<T>
+where
+    T: PartialEq,
+    P1: Parser<T>,
+    PB: Parser<()>,
+    P2: Parser<T>,
+{
+    bind(move |buf| {
+        let (buf, a) = first.parse(buf)?;
+        let (buf, _) = between.parse(buf)?;
+        let (rest, b) = second.parse(buf)?;
+        if a != b {
+            return par_err(skip_whitespace(buf), "does not match the value it pairs with");
+        }
+        Ok((rest, a))
+    })
+}
+
+/// Runs `a`, then the parser `f` builds from its output. This lets a grammar
+/// depend on what was already parsed, eg. a closing tag matching the opening.
+pub fn and_then<A, B, PA, PB, F> (a: PA, f: F) -> impl Parser<B> 
+where
+    PA: Parser<A>,
+    PB: Parser<B>,
+    F: Fn(A) -> PB,
+{
+    bind(move |buf| {
+        let (buf, o) = a.parse(buf)?;
+        f(o).parse(buf)
     })
+}
+
+pub fn or<P1, P2, A>(parser1: P1, parser2: P2) -> Or<P1, P2>
+where
+    P1: Parser<A>,
+    P2: Parser<A>,
+{
+    Or { parser1, parser2 }
 } 
 
+/// Runs every one of `parsers` on the input, keeping the match that consumed
+/// the most; the earliest wins a tie. Fails only if all of them fail.
+pub fn longest<'a, T: 'a> (parsers: Vec<BoxedParser<'a, T>>) -> impl Parser<T> + 'a {
+    bind(move |buf| {
+        let mut best: Option<(&str, T)> = None;
+        let mut err: Option<ParseErr> = None;
+        for p in parsers.iter() {
+            match p.parse(buf) {
+                Ok((rest, o)) => {
+                    if best.as_ref().is_none_or(|(b, _)| rest.len() < b.len()) {
+                        best = Some((rest, o));
+                    }
+                },
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(e) => err = Some(match err {
+                    Some(prev) => prev.merge(e),
+                    None       => e,
+                }),
+            }
+        }
+        match (best, err) {
+            (Some(ok), _)    => Ok(ok),
+            (None, Some(e))  => Err(e),
+            (None, None)     => par_err(buf, "no alternatives to parse"),
+        }
+    })
+}
+
+/// Ordered choice between `parsers` that runs every one of them, to debug a
+/// grammar: fails if two or more match the same length of input, listing them.
+pub fn ambiguity_check<'a, T: 'a> (parsers: Vec<BoxedParser<'a, T>>) -> impl Parser<T> + 'a {
+    bind(move |buf| {
+        let mut matches: Vec<(usize, &str, T)> = vec![];
+        let mut err: Option<ParseErr> = None;
+        for (i, p) in parsers.iter().enumerate() {
+            match p.parse(buf) {
+                Ok((rest, o)) => matches.push((i, rest, o)),
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(e) => err = Some(match err {
+                    Some(prev) => prev.merge(e),
+                    None       => e,
+                }),
+            }
+        }
+
+        for (_, rest, _) in matches.iter() {
+            let same: Vec<String> = matches.iter()
+                .filter(|(_, r, _)| r.len() == rest.len())
+                .map(|(i, _, _)| i.to_string())
+                .collect();
+            if same.len() > 1 {
+                return par_err_s(buf, format!("ambiguous alternatives {} match the same input", same.join(", ")));
+            }
+        }
+        match (matches.into_iter().next(), err) {
+            (Some((_, rest, o)), _) => Ok((rest, o)),
+            (None, Some(e))         => Err(e),
+            (None, None)            => par_err(buf, "no alternatives to parse"),
+        }
+    })
+}
+
+// Attaches a description to `p`, see `DescribableParser`
+pub fn described<T, P> (desc: &str, p: P) -> Described<P>
+where
+    P: Parser<T>
+{
+    Described { parser: p, desc: desc.to_owned() }
+}
+
 
 pub fn zero_or_more<'a, A, P> (p: P) -> impl Parser<Vec<A>> 
+where
+    P: Parser<A>
+{
+    repetition(p, 0, usize::MAX, false, "zero_or_more")
+}
+
+pub fn one_or_more<'a, A, P> (p: P) -> impl Parser<Vec<A>> 
+where
+    P: Parser<A>
+{
+    repetition(p, 1, usize::MAX, false, "one_or_more")
+}
+
+// Like `zero_or_more`, but fails rather than collect more than `max` items
+pub fn zero_or_more_capped<A, P> (p: P, max: usize) -> impl Parser<Vec<A>> 
+where
+    P: Parser<A>
+{
+    repetition(p, 0, max, true, "zero_or_more_capped")
+}
+
+pub fn one_or_more_capped<A, P> (p: P, max: usize) -> impl Parser<Vec<A>> 
+where
+    P: Parser<A>
+{
+    repetition(p, 1, max, true, "one_or_more_capped")
+}
+
+// Repeats `p`, failing with fewer than `min` matches. At `max` matches it stops,
+// or if `strict`, fails should another follow.
+fn repetition<A, P> (p: P, min: usize, max: usize, strict: bool, name: &'static str) -> impl Parser<Vec<A>> 
 where
     P: Parser<A>
 {
     bind(move |buf| {
         let mut v = vec![];
         let mut buf_out = buf;
-        while let Ok((buf, out)) = p.parse(buf_out) {
+        loop {
+            if v.len() == max && !strict { break }
+            let (buf, out) = match p.parse(buf_out) {
+                Ok(o)                  => o,
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(_)                 => break,
+            };
+            // A match that consumes nothing would repeat forever
+            if buf.len() == buf_out.len() { break }
+            if v.len() == max {
+                return par_err_s(buf_out, format!("more than {} of pattern found in '{}'", max, name));
+            }
             v.push(out);
 
             buf_out = buf;
         }
+        if v.is_empty() && min > 0 {
+            par_err_s(buf, format!("none of pattern found in '{}'", name))
+        } else if v.len() < min {
+            let bound = if min == max { "" } else { "at least " };
+            par_err_s(buf_out, format!("{} of pattern found in '{}', expected {}{}", v.len(), name, bound, min))
+        } else {
+            Ok((buf_out, v))
+        }
+    })
+}
+
+/// Zero or more of `p`, each with its span. Offsets are into the enclosing
+/// `with_origin` input, or else into the buffer this is run on.
+pub fn many_spanned<T, P> (p: P) -> impl Parser<Vec<Spanned<T>>>
+where
+    P: Parser<T>
+{
+    bind(move |buf: &str| {
+        let base = origin_offset(buf).unwrap_or(0) + buf.len();
+        let mut v = vec![];
+        let mut buf_out = buf;
+        loop {
+            let start = skip_whitespace(buf_out);
+            let (rest, value) = match p.parse(start) {
+                Ok(o)                  => o,
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(_)                 => break,
+            };
+            // A match that consumes nothing would repeat forever
+            if rest.len() == start.len() { break }
+            v.push(Spanned { value, start: base - start.len(), end: base - rest.len() });
+            buf_out = rest;
+        }
         Ok((buf_out, v))
     })
 }
 
-pub fn one_or_more<'a, A, P> (p: P) -> impl Parser<Vec<A>> 
+/// Zero or more of `p`, appended to `out` rather than collected into a new
+/// `Vec`. The parser borrows `out` mutably for as long as it lives, so drop it
+/// before reading the results; on failure, earlier matches are kept.
+pub fn fill<'v, T, P> (p: P, out: &'v mut Vec<T>) -> impl Parser<()> + 'v
+where
+    P: Parser<T> + 'v
+{
+    let out = RefCell::new(out);
+    bind(move |buf| {
+        let mut buf_out = buf;
+        loop {
+            let (buf, o) = match p.parse(buf_out) {
+                Ok(o)                  => o,
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(_)                 => break,
+            };
+            // A match that consumes nothing would repeat forever
+            if buf.len() == buf_out.len() { break }
+            // Only borrowed here, as `p` may reach this parser again
+            out.borrow_mut().push(o);
+            buf_out = buf;
+        }
+        Ok((buf_out, ()))
+    })
+}
+
+/// How many times `repeat` matches its parser, written like a regex count as
+/// `{n}`, `{m,}`, `{,n}` or `{m,n}`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RepeatSpec {
+    Exactly(usize),
+    AtLeast(usize),
+    AtMost(usize),
+    Between(usize, usize),
+}
+impl std::str::FromStr for RepeatSpec {
+    type Err = String;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let body = s.strip_prefix('{').and_then(|s| s.strip_suffix('}'))
+            .ok_or_else(|| format!("repetition count '{}' must be within braces", s))?;
+        let num = |n: &str| n.trim().parse::<usize>().map_err(|_| format!("malformed repetition count '{}'", s));
+
+        let spec = match body.split_once(',') {
+            None => RepeatSpec::Exactly(num(body)?),
+            Some((m, n)) if n.trim().is_empty() => RepeatSpec::AtLeast(num(m)?),
+            Some((m, n)) if m.trim().is_empty() => RepeatSpec::AtMost(num(n)?),
+            Some((m, n)) => RepeatSpec::Between(num(m)?, num(n)?),
+        };
+        match spec {
+            RepeatSpec::Between(m, n) if m > n => Err(format!("repetition count '{}' has min above max", s)),
+            spec => Ok(spec),
+        }
+    }
+}
+
+/// `p` as many times as `spec` allows, failing on too few. Matching stops at
+/// the maximum, leaving any further input; see the `_capped` helpers to fail instead.
+pub fn repeat<T, P> (p: P, spec: RepeatSpec) -> impl Parser<Vec<T>>
+where
+    P: Parser<T>
+{
+    let (min, max) = match spec {
+        RepeatSpec::Exactly(n)    => (n, n),
+        RepeatSpec::AtLeast(n)    => (n, usize::MAX),
+        RepeatSpec::AtMost(n)     => (0, n),
+        RepeatSpec::Between(m, n) => (m, n),
+    };
+    repetition(p, min, max, false, "repeat")
+}
+
+// Exactly `n` of `p`, the same as `repeat` with `RepeatSpec::Exactly(n)`
+pub fn count<A, P> (p: P, n: usize) -> impl Parser<Vec<A>> 
 where
     P: Parser<A>
 {
+    repetition(p, n, n, false, "count")
+}
+
+/// Zero or more `p` separated by the literal `sep`. A trailing separator is
+/// not consumed.
+pub fn sep_by<T, P> (p: P, sep: &str) -> impl Parser<Vec<T>>
+where
+    P: Parser<T>
+{
+    separated(p, sep, 0, usize::MAX, "sep_by")
+}
+
+pub fn sep_by1<T, P> (p: P, sep: &str) -> impl Parser<Vec<T>>
+where
+    P: Parser<T>
+{
+    separated(p, sep, 1, usize::MAX, "sep_by1")
+}
+
+// Like `sep_by`, but fails rather than collect more than `max` items
+pub fn sep_by_capped<T, P> (p: P, sep: &str, max: usize) -> impl Parser<Vec<T>>
+where
+    P: Parser<T>
+{
+    separated(p, sep, 0, max, "sep_by_capped")
+}
+
+// Separated repetition of `p`, failing with fewer than `min` or more than `max` matches
+fn separated<T, P> (p: P, sep: &str, min: usize, max: usize, name: &'static str) -> impl Parser<Vec<T>>
+where
+    P: Parser<T>
+{
+    let sep = parse_literal(sep);
     bind(move |buf| {
         let mut v = vec![];
         let mut buf_out = buf;
-        while let Ok((buf, out)) = p.parse(buf_out) {
-            v.push(out);
-
-            buf_out = buf;
+        let mut next = buf;
+        loop {
+            match p.parse(next) {
+                Ok(_) if v.len() == max => {
+                    return par_err_s(next, format!("more than {} of pattern found in '{}'", max, name));
+                },
+                Ok((buf, o)) => { v.push(o); buf_out = buf; },
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(_) => break,
+            }
+            match sep.parse(buf_out) {
+                Ok((buf, _)) => next = buf,
+                Err(_) => break,
+            }
         }
-        if v.is_empty() {
-            par_err(buf, "none of pattern found in 'one_or_more'")
+        if v.len() < min {
+            par_err_s(buf, format!("none of pattern found in '{}'", name))
         } else {
             Ok((buf_out, v))
         }
     })
 }
 
+/// Like `sep_by`, but with a separator parser whose values are kept. Returns
+/// the items, and the separators between them: one fewer, unless both are empty.
+pub fn sep_by_keeping<T, S, P, SP> (item: P, sep: SP) -> impl Parser<(Vec<T>, Vec<S>)>
+where
+    P: Parser<T>,
+    SP: Parser<S>
+{
+    bind(move |buf| {
+        let (mut items, mut seps) = (vec![], vec![]);
+        let mut buf_out = buf;
+        let mut next = buf;
+        let mut pending = None;
+        loop {
+            match item.parse(next) {
+                Ok((buf, o)) => { items.push(o); buf_out = buf; },
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(_) => break,
+            }
+            // A separator only counts once an item follows it
+            seps.extend(pending.take());
+            match sep.parse(buf_out) {
+                Ok((buf, s)) => { pending = Some(s); next = buf; },
+                Err(_) => break,
+            }
+        }
+        Ok((buf_out, (items, seps)))
+    })
+}
+
+/// Chained comparison such as `a < b <= c`, as its links `(a, <, b)` and
+/// `(b, <=, c)`, each sharing an operand with the next. Needs at least one `op`.
+pub fn comparison_chain<T, O, P, PO> (operand: P, op: PO) -> impl Parser<Vec<(T, O, T)>>
+where
+    T: Clone,
+    P: Parser<T>,
+    PO: Parser<O>
+{
+    let chain = sep_by_keeping(operand, op);
+    bind(move |buf| {
+        let (rest, (operands, ops)) = chain.parse(buf)?;
+        if ops.is_empty() {
+            return par_err(rest, "expected comparison operator");
+        }
+        let links = operands.windows(2).zip(ops)
+            .map(|(w, op)| (w[0].clone(), op, w[1].clone()))
+            .collect();
+        Ok((rest, links))
+    })
+}
+
+/// Table of `cell`s, with columns separated by `col_sep` and rows by `row_sep`,
+/// eg. "\n". Every row must have as many columns as the first. A trailing
+/// `row_sep` is allowed.
+pub fn grid<T, P> (cell: P, col_sep: &str, row_sep: &str) -> impl Parser<Vec<Vec<T>>>
+where
+    P: Parser<T>
+{
+    let row = sep_by1(cell, col_sep);
+    // Taken as-is, as whitespace such as a "\n" would otherwise be skipped
+    let row_sep = raw_literal(row_sep);
+    bind(move |buf| {
+        let mut rows: Vec<Vec<T>> = vec![];
+        let mut next = buf;
+        loop {
+            let (rest, cells) = match row.parse(next) {
+                Ok(o) => o,
+                Err(e) if rows.is_empty() || e.is_fatal() => return Err(e),
+                Err(_) => return Ok((next, rows)),
+            };
+            if let Some(first) = rows.first().filter(|first| first.len() != cells.len()) {
+                let msg = format!("row {} has {} columns, expected {}", rows.len() + 1, cells.len(), first.len());
+                return par_err_s(next.trim_start(), msg);
+            }
+            rows.push(cells);
+
+            match row_sep.parse(rest.trim_start_matches([' ', '\t'])) {
+                Ok((buf, _)) => next = buf,
+                Err(_) => return Ok((rest, rows)),
+            }
+        }
+    })
+}
+
+/// List such as `[a, b, c,]`: comma separated items with an optional trailing
+/// comma, between `open` and `close`. Lists may be empty.
+pub fn bracketed_list<T, P> (open: &str, item: P, close: &str) -> impl Parser<Vec<T>>
+where
+    P: Parser<T>
+{
+    // A trailing comma must follow an item
+    let items = sep_by(item, ",");
+    let comma = skip(option(parse_literal(",")));
+    let trailing = bind(move |buf| match items.parse(buf)? {
+        (buf, v) if v.is_empty() => Ok((buf, v)),
+        (buf, v) => comma.parse(buf).map(|(buf, _)| (buf, v)),
+    });
+    surround(open, close, trailing)
+}
+
+/// Call arguments such as `(a, b, c)`: comma separated, possibly none
+pub fn arg_list<T, P> (arg: P) -> impl Parser<Vec<T>>
+where
+    P: Parser<T>
+{
+    surround("(", ")", sep_by(arg, ","))
+}
+
+/// Commits to `p`: its failure becomes fatal, so enclosing `or`, `option` and
+/// repetitions report it rather than trying alternatives.
+pub fn cut<T, P> (p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    bind(move |buf| {
+        p.parse(buf).map_err(|e| ParseErr { fatal: true, ..e })
+    })
+}
+
+/// `prefix` then `rest`, with no way back once `prefix` has matched: a failure
+/// in `rest` is fatal, so no other alternative is tried for the input.
+pub fn commit_after<A, B, PA, PB> (prefix: PA, rest: PB) -> impl Parser<(A, B)>
+where
+    PA: Parser<A>,
+    PB: Parser<B>
+{
+    and(prefix, cut(rest))
+}
+
+/// Backtracks fully out of `p` on failure: the error is reported at the
+/// original position and is never fatal, undoing any `cut` within.
+pub fn attempt<T, P> (p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    bind(move |buf| {
+        p.parse(buf).map_err(|e| ParseErr { fatal: false, ..e.with_ptr(buf) })
+    })
+}
+
+/// Alternates `a` and `b`, starting with `a`, until the next one fails. A
+/// trailing `a` without its `b` is kept, so `a`'s list may be one longer.
+pub fn interleaved<A, B, PA, PB> (a: PA, b: PB) -> impl Parser<(Vec<A>, Vec<B>)> 
+where
+    PA: Parser<A>,
+    PB: Parser<B>,
+{
+    bind(move |buf| {
+        let mut va = vec![];
+        let mut vb = vec![];
+        let mut buf_out = buf;
+        loop {
+            match a.parse(buf_out) {
+                Ok((buf, o))           => { va.push(o); buf_out = buf; },
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(_)                 => break,
+            }
+            match b.parse(buf_out) {
+                Ok((buf, o))           => { vb.push(o); buf_out = buf; },
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(_)                 => break,
+            }
+        }
+        Ok((buf_out, (va, vb)))
+    })
+}
+
+pub fn skip<T, P> (p: P) -> impl Parser<()>
+where
+    P: Parser<T>
+{
+    map(p, |_| ())
+}
+
+pub fn skip_many<T, P> (p: P) -> impl Parser<()>
+where
+    P: Parser<T>
+{
+    map(zero_or_more(skip(p)), |_| ())
+}
+
+pub fn skip_many1<T, P> (p: P) -> impl Parser<()>
+where
+    P: Parser<T>
+{
+    map(one_or_more(skip(p)), |_| ())
+}
+
 pub fn prefix<'a, T, P> (s: &'a str, p: P) -> impl Parser<T>
 where
     P: Parser<T>
@@ -128,37 +690,154 @@ where
     )
 }
 
-pub fn parse_literal (lit: &str) -> impl Parser<String> {
+pub fn parse_literal (lit: &str) -> impl Parser<String> + DescribableParser {
+    let desc = format!("the literal '{}'", lit);
+    let lit = lit.to_owned();
+    described(&desc, bind(move |buf: & str| match buf.get(0..lit.len()) {
+        Some(s) if s == lit => Ok((&buf[lit.len()..], lit.clone())),
+        _ => Err(ParseErr::new(format!("Literal '{}' not found", lit), buf).expecting(format!("'{}'", lit)))
+    }))
+}
+
+
+// Like `parse_literal`, but matches as-is without skipping whitespace first
+pub fn raw_literal (lit: &str) -> impl Parser<String> {
     let lit = lit.to_owned();
-    bind(move |buf: & str| match buf.get(0..lit.len()) {
+    raw(move |buf: & str| match buf.get(0..lit.len()) {
         Some(s) if s == lit => Ok((&buf[lit.len()..], lit.clone())),
         _ => par_err_s(buf, format!("Literal '{}' not found", lit))
     })
 }
 
 
-pub fn parse_literals (lits: Vec<&str>) -> impl Parser<String> {
+pub fn parse_literals (lits: Vec<&str>) -> impl Parser<String> + DescribableParser {
+    let desc = lits.iter()
+        .map(|s| format!("the literal '{}'", s))
+        .collect::<Vec<_>>()
+        .join(", ");
     let lits: Vec<String> = lits.into_iter().map(|s| s.to_owned()).collect();
-    bind(move |buf: &str| {
+    described(&format!("one of: {}", desc), bind(move |buf: &str| {
         for lit in lits.iter() {
             match buf.get(0..lit.len()) {
                 Some(s) if &s == lit => return Ok((&buf[lit.len()..], lit.clone())),
                 _ => continue
             }
         }
-        par_err_s(buf, format!("Literal '{:?}' not found", lits))
+        let err = ParseErr::new(format!("Literal '{:?}' not found", lits), buf);
+        Err(lits.iter().fold(err, |err, lit| err.expecting(format!("'{}'", lit))))
+    }))
+}
+
+/// The longest of the literals in `pairs`, as its value
+pub fn literal_map<T: Clone> (pairs: &[(&str, T)]) -> impl Parser<T> {
+    let pairs: Vec<(String, T)> = pairs.iter().map(|(s, v)| (s.to_string(), v.clone())).collect();
+    bind(move |buf: &str| {
+        let found = pairs.iter()
+            .filter(|(lit, _)| buf.starts_with(lit.as_str()))
+            .max_by_key(|(lit, _)| lit.len());
+        match found {
+            Some((lit, v)) => Ok((&buf[lit.len()..], v.clone())),
+            None => {
+                let lits: Vec<&str> = pairs.iter().map(|(lit, _)| lit.as_str()).collect();
+                let err = ParseErr::new(format!("Literal '{:?}' not found", lits), buf);
+                Err(lits.iter().fold(err, |err, lit| err.expecting(format!("'{}'", lit))))
+            }
+        }
     })
 }
 
-pub fn parse_tok_with_rule<R> (rule: R) -> impl Parser<String> 
-where
-    R: Fn (char) -> bool
-{
+/// Matches the longest of `words`, ignoring ASCII case, as a whole word: the
+/// match must not run on into an identifier. Returns the text as written.
+pub fn keyword_of (words: &[&str]) -> impl Parser<String> {
+    let words: Vec<String> = words.iter().map(|s| s.to_string()).collect();
     bind(move |buf: &str| {
-        let mut tok = String::new();
-        let mut iter = buf.chars();
+        let found = words.iter()
+            .filter(|w| match buf.get(0..w.len()) {
+                Some(s) => s.eq_ignore_ascii_case(w),
+                None    => false
+            })
+            .filter(|w| !buf[w.len()..].starts_with(is_ident_char))
+            .map(|w| w.len())
+            .max();
 
-        match iter.next() {
+        match found {
+            Some(len) => Ok((&buf[len..], buf[..len].to_owned())),
+            None      => par_err_s(buf, format!("Keyword '{:?}' not found", words))
+        }
+    })
+}
+
+/// One or more of the flag names in `pairs`, separated by `sep`, as their
+/// values. Names are matched longest first; an unknown name is an error.
+pub fn flags_of<T: Clone> (pairs: Vec<(&str, T)>, sep: &str) -> impl Parser<Vec<T>> {
+    let names = Rc::new(literal_map(&pairs));
+    let flag = bind(move |buf: &str| names.parse(buf).map_err(|e| {
+        let name = word(buf).map_or(String::new(), |(_, w)| w);
+        ParseErr { msg: format!("unknown flag '{}'", name), ..e }
+    }));
+
+    let list = sep_by1(flag.clone(), sep);
+    let sep = parse_literal(sep);
+    bind(move |buf: &str| {
+        // `sep_by1` would only say nothing was found
+        flag.parse(buf)?;
+        let (rest, v) = list.parse(buf)?;
+        // `sep_by1` stops before a separator not followed by a flag
+        if let Ok((after, _)) = sep.parse(rest) {
+            flag.parse(after)?;
+        }
+        Ok((rest, v))
+    })
+}
+
+/// One of `keywords`, as a whole word. When the word found is a near miss of
+/// one, the error suggests it.
+pub fn keyword_suggest (keywords: &[&str]) -> impl Parser<String> {
+    let keywords: Vec<String> = keywords.iter().map(|s| s.to_string()).collect();
+    bind(move |buf: &str| {
+        let (rest, found) = word(buf)?;
+        if keywords.contains(&found) {
+            return Ok((rest, found));
+        }
+
+        // Allow more typos in longer keywords
+        let near = keywords.iter()
+            .map(|kw| (levenshtein(&found, kw), kw))
+            .filter(|(d, kw)| *d <= (kw.len() / 3).clamp(1, 2))
+            .min_by_key(|(d, _)| *d);
+        match near {
+            Some((_, kw)) => par_err_s(buf, format!("unknown keyword '{}', did you mean '{}'?", found, kw)),
+            None          => par_err_s(buf, format!("unknown keyword '{}'", found)),
+        }
+    })
+}
+
+// Edit distance between `a` and `b`, in characters
+fn levenshtein (a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let sub = diag + (ca != *cb) as usize;
+            diag = row[j + 1];
+            row[j + 1] = sub.min(row[j] + 1).min(diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+
+pub fn parse_tok_with_rule<R> (rule: R) -> impl Parser<String> 
+where
+    R: Fn (char) -> bool
+{
+    bind(move |buf: &str| {
+        let mut tok = String::new();
+        let mut iter = buf.chars();
+
+        match iter.next() {
             Some(c) if rule(c) => tok.push(c),
             _ => return par_err(buf, "First character does not satisfy rule")
         }
@@ -176,11 +855,443 @@ where
 }
 
 
-pub fn parse_number<'a> (buf: &'a str) -> ParseRes<'a, f64> {
+/// Counts the leading characters satisfying `rule`, returning the count along
+/// with the matched text. Whitespace is not skipped, and zero matches succeed.
+pub fn count_while<R> (rule: R) -> impl Parser<(usize, String)> 
+where
+    R: Fn (char) -> bool
+{
+    raw(move |buf: &str| {
+        let mut count = 0;
+        let mut end = buf.len();
+        for (i, c) in buf.char_indices() {
+            if !rule(c) {
+                end = i;
+                break;
+            }
+            count += 1;
+        }
+        Ok((&buf[end..], (count, buf[..end].to_owned())))
+    })
+}
+
+/// Width of the leading spaces and tabs, each tab counting `tab_width`
+/// columns. Stops before the first other character; zero width succeeds.
+pub fn indentation (tab_width: usize) -> impl Parser<usize> {
+    raw(move |buf: &str| {
+        let end = buf.find(|c| c != ' ' && c != '\t').unwrap_or(buf.len());
+        let width = buf[..end].chars().map(|c| if c == '\t' { tab_width } else { 1 }).sum();
+        Ok((&buf[end..], width))
+    })
+}
+
+
+
+/// Takes everything up to (not including) the first character in `delims`, or
+/// to the end of the buffer. The field may be empty, and is taken verbatim.
+pub fn field (delims: &str) -> impl Parser<String> {
+    let delims = delims.to_owned();
+    raw(move |buf: &str| {
+        let end = buf.find(|c| delims.contains(c)).unwrap_or(buf.len());
+        Ok((&buf[end..], buf[..end].to_owned()))
+    })
+}
+
+
+/// Takes everything up to the first char `stop` accepts, or to the end, as
+/// words joined by single spaces: whitespace is trimmed and runs collapsed.
+pub fn normalized_text<S> (stop: S) -> impl Parser<String>
+where
+    S: Fn(char) -> bool
+{
+    raw(move |buf: &str| {
+        let end = buf.find(&stop).unwrap_or(buf.len());
+        Ok((&buf[end..], buf[..end].split_whitespace().collect::<Vec<_>>().join(" ")))
+    })
+}
+
+
+/// Takes everything up to (not including) the first occurrence of `lit`.
+/// Fails if `lit` is never found.
+pub fn take_until (lit: &str) -> impl Parser<String> {
+    let lit = lit.to_owned();
+    raw(move |buf: &str| match buf.find(lit.as_str()) {
+        Some(end) => Ok((&buf[end..], buf[..end].to_owned())),
+        None      => par_err_s(buf, format!("Literal '{}' not found", lit))
+    })
+}
+
+
+/// Reads one logical line, consuming its line ending. A physical line ending
+/// in `\` continues onto the next; the two are joined without the backslash.
+/// A continuation at the end of input continues onto nothing.
+pub fn logical_line () -> impl Parser<String> {
+    raw(|buf: &str| {
+        if buf.is_empty() {
+            return par_err(buf, "expected line, found end of input");
+        }
+
+        let mut line = String::new();
+        let mut rest = buf;
+        loop {
+            let (phys, ending, next) = split_line(rest);
+            rest = next;
+
+            match phys.strip_suffix('\\') {
+                Some(s) if !ending.is_empty() => {
+                    line += s;
+                    if rest.is_empty() {
+                        return Ok((rest, line));
+                    }
+                },
+                _ => {
+                    line += phys;
+                    return Ok((rest, line));
+                }
+            }
+        }
+    })
+}
+
+
+// Matches a "\n", "\r\n" or lone "\r" as-is, without skipping it as
+// whitespace first
+pub fn line_ending () -> impl Parser<String> {
+    raw(|buf: &str| {
+        let len = if buf.starts_with("\r\n") { 2 } else if buf.starts_with(['\n', '\r']) { 1 } else { 0 };
+        if len == 0 {
+            return par_err(buf, "expected line ending");
+        }
+        Ok((&buf[len..], buf[..len].to_owned()))
+    })
+}
+
+/// Splits `buf` at its first line ending, as `line_ending` takes them, into
+/// the line, the ending and the rest. The ending is empty on the last line.
+pub fn split_line (buf: &str) -> (&str, &str, &str) {
+    let Some(i) = buf.find(['\n', '\r']) else {
+        return (buf, "", "");
+    };
+    let len = if buf[i..].starts_with("\r\n") { 2 } else { 1 };
+    (&buf[..i], &buf[i..i + len], &buf[i + len..])
+}
+
+/// `p`, after a leading UTF-8 byte order mark if there is one. The input is
+/// not copied, so offsets are still those of the original buffer.
+pub fn strip_bom<T, P> (p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    raw(move |buf: &str| p.parse(buf.strip_prefix('\u{FEFF}').unwrap_or(buf)))
+}
+
+
+/// Parses `key <sep> value` entries separated by `line_sep` into a map. Values
+/// run to the end of the line; keys and values are trimmed. Keys must be unique
+/// words. Blank input is an empty map, but a malformed first entry is an error.
+/// `line_sep` is run as-is, so pass a raw parser like `line_ending` if it must
+/// see the whitespace other parsers skip.
+pub fn key_values<U, S> (sep: &str, line_sep: S) -> impl Parser<HashMap<String, String>>
+where
+    S: Parser<U>
+{
+    let entry = and(suffix(sep, word), field("\r\n"));
+    bind(move |buf: &str| {
+        let mut map = HashMap::new();
+        if buf.is_empty() {
+            return Ok((buf, map));
+        }
+        entry.parse(buf)?;
+
+        // Only consume a separator when an entry follows it
+        let mut buf_out = buf;
+        let mut next = buf;
+        while let Ok((buf, (key, val))) = entry.parse(next) {
+            if map.contains_key(&key) {
+                return par_err_s(next.trim_start(), format!("duplicate key '{}'", key));
+            }
+            map.insert(key, val.trim().to_owned());
+            buf_out = buf;
+
+            match line_sep.parse(buf) {
+                Ok((buf, _)) => next = buf,
+                Err(_)       => break,
+            }
+        }
+        Ok((buf_out, map))
+    })
+}
+
+
+pub fn eof<'a> (buf: &'a str) -> ParseRes<'a, ()> {
+    if buf.is_empty() {
+        Ok((buf, ()))
+    } else {
+        par_err(buf, "expected end of input")
+    }
+}
+
+
+/// Parses a suffix of the buffer rather than a prefix: the longest suffix that
+/// `p` consumes fully, found by scanning back from the end. This inverts the
+/// usual direction, as the remaining buffer is everything *before* the suffix,
+/// eg. `"100"` after taking the unit of `"100px"`. That buffer ends early, so
+/// follow it with parsers expecting the text before the suffix, not after.
+pub fn from_end<T, P> (p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    raw(move |buf: &str| {
+        let mut found = None;
+        for (start, _) in buf.char_indices().rev() {
+            match p.parse(&buf[start..]) {
+                Ok((rest, o)) if rest.trim().is_empty() => found = Some((start, o)),
+                _ if found.is_some() => break,
+                _ => continue,
+            }
+        }
+        match found {
+            Some((start, o)) => Ok((&buf[..start], o)),
+            None => par_err(&buf[buf.len()..], "suffix not found"),
+        }
+    })
+}
+
+/// Runs `p`, failing if it consumes more than `max_bytes` of the input, to
+/// bound the work done on untrusted input. `p` only sees the input up to the
+/// first character past the limit, so it cannot scan any further.
+pub fn with_input_limit<T, P> (max_bytes: usize, p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    raw(move |buf: &str| {
+        let mut end = (max_bytes + 1).min(buf.len());
+        while !buf.is_char_boundary(end) { end += 1 }
+
+        let (rest, o) = p.parse(&buf[..end])?;
+        let consumed = end - rest.len();
+        if consumed > max_bytes {
+            let mut end = max_bytes;
+            while !buf.is_char_boundary(end) { end -= 1 }
+            return par_err_s(&buf[end..], format!("input exceeds the limit of {} bytes", max_bytes));
+        }
+        Ok((&buf[consumed..], o))
+    })
+}
+
+/// Runs `inner` on the text captured by `outer`, which it must consume fully.
+/// The remaining buffer is whatever `outer` left.
+pub fn map_parser<T, PO, PI> (outer: PO, inner: PI) -> impl Parser<T>
+where
+    PO: Parser<String>,
+    PI: Parser<T>,
+{
+    bind(move |buf: &str| {
+        let (rest, chunk) = outer.parse(buf)?;
+        match inner.parse(chunk.as_str()).and_then(|(b, o)| eof.parse(b).map(|_| o)) {
+            Ok(o)  => Ok((rest, o)),
+            Err(e) => par_err_s(buf, format!("inner parser failed: {}", e.msg)),
+        }
+    })
+}
+
+
+/// Starting at `open`, takes everything up to the matching `close`, accounting
+/// for nested pairs. Returns the contents without the outermost delimiters.
+pub fn balanced (open: char, close: char) -> impl Parser<String> {
+    bind(move |buf: &str| {
+        if !buf.starts_with(open) {
+            return par_err_s(buf, format!("Literal '{}' not found", open));
+        }
+        match matching_close(buf, open, close) {
+            Some(i) => {
+                let inner = &buf[open.len_utf8()..i];
+                Ok((&buf[i + close.len_utf8()..], inner.to_owned()))
+            },
+            None => par_err_s(buf, format!("unbalanced '{}', missing '{}'", open, close))
+        }
+    })
+}
+
+/// Like `balanced`, but a missing `close` is tolerated by taking the rest of
+/// the buffer. Also returns whether the contents were properly closed.
+pub fn balanced_tolerant (open: char, close: char) -> impl Parser<(String, bool)> {
+    bind(move |buf: &str| {
+        if !buf.starts_with(open) {
+            return par_err_s(buf, format!("Literal '{}' not found", open));
+        }
+        match matching_close(buf, open, close) {
+            Some(i) => {
+                let inner = &buf[open.len_utf8()..i];
+                Ok((&buf[i + close.len_utf8()..], (inner.to_owned(), true)))
+            },
+            None => Ok((&buf[buf.len()..], (buf[open.len_utf8()..].to_owned(), false)))
+        }
+    })
+}
+
+// Byte index of the `close` matching the `open` that `buf` starts with
+fn matching_close (buf: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in buf.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 { return Some(i) }
+        }
+    }
+    None
+}
+
+/// Block comment between `open` and `close`, which may nest, eg. Rust's
+/// `/* a /* b */ c */`. The whole comment is consumed.
+pub fn nested_block_comment (open: &str, close: &str) -> impl Parser<()> {
+    let (open, close) = (open.to_owned(), close.to_owned());
+    bind(move |buf: &str| {
+        if !buf.starts_with(open.as_str()) {
+            return par_err_s(buf, format!("Literal '{}' not found", open));
+        }
+
+        let mut depth = 0;
+        let mut rest = buf;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with(open.as_str()) {
+                depth += 1;
+                rest = &rest[open.len()..];
+            } else if rest.starts_with(close.as_str()) {
+                depth -= 1;
+                rest = &rest[close.len()..];
+                if depth == 0 { return Ok((rest, ())) }
+            } else {
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        par_err_s(buf, format!("unterminated comment, missing '{}'", close))
+    })
+}
+
+
+// A single decimal digit, taken as-is
+pub fn digit () -> impl Parser<u32> {
+    raw(|buf: &str| match buf.chars().next().and_then(|c| c.to_digit(10)) {
+        Some(d) => Ok((&buf[1..], d)),
+        None    => par_err(buf, "expected digit")
+    })
+}
+
+
+/// A single character in the class `spec`, taken as-is: ranges such as `a-z`
+/// and single characters, negated by a leading `^`. A `-` at either end is
+/// literal. Panics on a backwards range.
+pub fn char_class (spec: &str) -> impl Parser<char> {
+    let (negated, body) = match spec.strip_prefix('^') {
+        Some(body) => (true, body),
+        None       => (false, spec),
+    };
+
+    let chars: Vec<char> = body.chars().collect();
+    let mut ranges = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            assert!(chars[i] <= chars[i + 2], "malformed range '{}-{}' in char class '{}'", chars[i], chars[i + 2], spec);
+            ranges.push(chars[i]..=chars[i + 2]);
+            i += 3;
+        } else {
+            ranges.push(chars[i]..=chars[i]);
+            i += 1;
+        }
+    }
+
+    let spec = spec.to_owned();
+    raw(move |buf: &str| match buf.chars().next() {
+        Some(c) if ranges.iter().any(|r| r.contains(&c)) != negated => Ok((&buf[c.len_utf8()..], c)),
+        _ => par_err_s(buf, format!("expected character in [{}]", spec))
+    })
+}
+
+
+// Exactly `n` bytes, taken as-is. Fails if that would split a character.
+pub fn take (n: usize) -> impl Parser<String> {
+    raw(move |buf: &str| match buf.get(0..n) {
+        Some(s) => Ok((&buf[n..], s.to_owned())),
+        None    => par_err_s(buf, format!("expected {} bytes", n))
+    })
+}
+
+
+// Decimal integer
+pub fn parse_int<'a> (buf: &'a str) -> ParseRes<'a, i64> {
+    let (rest, tok) = parse_tok_with_rule(|c| c.is_ascii_digit()).parse(buf)?;
+    match tok.parse::<i64>() {
+        Ok(n)  => Ok((rest, n)),
+        Err(_) => par_err(buf, "integer out of range")
+    }
+}
+
+
+/// Optional `+` or `-` directly before `p`, negating its result on `-`
+pub fn signed<T, P> (p: P) -> impl Parser<T>
+where
+    T: Neg<Output = T>,
+    P: Parser<T>,
+{
+    bind(move |buf: &str| {
+        let (neg, rest) = match buf.chars().next() {
+            Some('-') => (true, &buf[1..]),
+            Some('+') => (false, &buf[1..]),
+            _         => (false, buf),
+        };
+        if rest.len() < buf.len() && rest.starts_with(char::is_whitespace) {
+            return par_err(rest, "unexpected whitespace after sign");
+        }
+        let (rest, n) = p.parse(rest)?;
+        Ok((rest, if neg { -n } else { n }))
+    })
+}
+
+
+/// Number directly followed by one of the unit suffixes in `units`, eg. `500ms`,
+/// as the magnitude and the unit's value. The longest unit that ends the word
+/// is taken.
+pub fn quantity<U: Clone> (units: &[(&str, U)]) -> impl Parser<(f64, U)> {
+    let units: Vec<(String, U)> = units.iter().map(|(s, u)| (s.to_string(), u.clone())).collect();
+    bind(move |buf: &str| {
+        let (rest, n) = parse_number(buf)?;
+        let found = units.iter()
+            .filter(|(unit, _)| rest.starts_with(unit.as_str()) && !rest[unit.len()..].starts_with(is_ident_char))
+            .max_by_key(|(unit, _)| unit.len());
+        match found {
+            Some((unit, u)) => Ok((&rest[unit.len()..], (n, u.clone()))),
+            None => par_err_s(rest, format!("expected unit after {}", n)),
+        }
+    })
+}
+
+
+// Scans the lexical form of a number, shared by the numeric parsers, and so by
+// the interpreter's number literals: `1e5` is one number, not `1` then `e5`
+pub fn recognize_float<'a> (buf: &'a str) -> ParseRes<'a, String> {
     let num_rule = |c: char| {
         c.is_ascii_digit() || c == '.'
     };
-    let (buf, tok) = parse_tok_with_rule(num_rule).parse(buf)?;
+    let (rest, mut tok) = parse_tok_with_rule(num_rule).parse(buf)?;
+
+    // Exponent, only if it has digits, eg. the 'e' in `2else` is not one
+    let sign = rest.get(1..2).filter(|s| *s == "+" || *s == "-").map_or(0, |_| 1);
+    let digits = rest.get(1 + sign..).map_or(0, |s| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    if (rest.starts_with('e') || rest.starts_with('E')) && digits > 0 {
+        let len = 1 + sign + digits;
+        tok += &rest[..len];
+        return Ok((&rest[len..], tok));
+    }
+    Ok((rest, tok))
+}
+
+
+pub fn parse_number<'a> (buf: &'a str) -> ParseRes<'a, f64> {
+    let (buf, tok) = recognize_float(buf)?;
     if let Ok(num) = tok.parse::<f64>() {
         Ok((buf, num))
     } else {
@@ -189,33 +1300,1577 @@ pub fn parse_number<'a> (buf: &'a str) -> ParseRes<'a, f64> {
 }
 
 
-pub fn parse_identifier<'a> (buf: &'a str) -> ParseRes<'a, String> {
-    let rule = |c: char| {
-        c.is_alphanumeric() || c == '_'
+pub fn parse_f32<'a> (buf: &'a str) -> ParseRes<'a, f32> {
+    let (buf, tok) = recognize_float(buf)?;
+    if let Ok(num) = tok.parse::<f32>() {
+        Ok((buf, num))
+    } else {
+        par_err(buf, "could not parse into number")
+    }
+}
+
+
+/// Exact fixed-point number, valued at `digits * 10^-scale`
+#[cfg(feature = "decimal")]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Decimal {
+    pub digits: i128,
+    pub scale: u32,
+}
+
+#[cfg(feature = "decimal")]
+pub fn parse_decimal<'a> (buf: &'a str) -> ParseRes<'a, Decimal> {
+    let (rest, tok) = recognize_float(buf)?;
+
+    let (int, frac) = tok.split_once('.').unwrap_or((tok.as_str(), ""));
+    if frac.contains('.') || tok.contains(['e', 'E']) || (int.is_empty() && frac.is_empty()) {
+        return par_err(buf, "could not parse into decimal");
+    }
+
+    let mut digits: i128 = 0;
+    for c in int.chars().chain(frac.chars()) {
+        let d = c.to_digit(10).unwrap() as i128;
+        match digits.checked_mul(10).and_then(|v| v.checked_add(d)) {
+            Some(v) => digits = v,
+            None    => return par_err(buf, "decimal out of range"),
+        }
+    }
+    Ok((rest, Decimal { digits, scale: frac.len() as u32 }))
+}
+
+
+const INT_SUFFIXES: [&str; 12] = [
+    "i8", "i16", "i32", "i64", "i128", "isize",
+    "u8", "u16", "u32", "u64", "u128", "usize",
+];
+
+/// Integer literal in Rust syntax: an optional `0x`/`0o`/`0b` prefix, digits
+/// with `_` separators, and an optional type suffix which is discarded.
+pub fn parse_rust_int<'a> (buf: &'a str) -> ParseRes<'a, i64> {
+    let (radix, rest) = match buf.get(0..2) {
+        Some("0x") => (16, &buf[2..]),
+        Some("0o") => (8,  &buf[2..]),
+        Some("0b") => (2,  &buf[2..]),
+        _          => (10, buf),
     };
-    let (buf, tok) = parse_tok_with_rule(rule).parse(buf)?;
 
-    if tok.chars().next().unwrap().is_ascii_digit() { return par_err(buf, "identifier cannot start with digit") }
-    if KEYWORDS.contains(&tok.as_str())             { return par_err(buf, "found keyword, cannot be used as identifier") }
+    let end = rest.find(|c: char| !(c.is_digit(radix) || c == '_')).unwrap_or(rest.len());
+    let tok = &rest[..end];
+    if tok.is_empty() || tok.starts_with('_') || tok.contains("__") {
+        return par_err(buf, "malformed integer literal");
+    }
 
-    Ok((buf, tok))
+    let mut num: i64 = 0;
+    for d in tok.chars().filter_map(|c| c.to_digit(radix)) {
+        match num.checked_mul(radix as i64).and_then(|v| v.checked_add(d as i64)) {
+            Some(v) => num = v,
+            None    => return par_err(buf, "integer literal out of range"),
+        }
+    }
+
+    let rest = &rest[end..];
+    let rest = match INT_SUFFIXES.iter().find(|s| rest.starts_with(*s)) {
+        Some(s) => &rest[s.len()..],
+        None    => rest,
+    };
+    if rest.starts_with(is_ident_char) {
+        return par_err(rest, "invalid suffix on integer literal");
+    }
+    Ok((rest, num))
 }
 
 
-pub fn map<'a, A, B, P, F> (parser: P, functor: F) -> impl Parser<B> 
-where 
-    P: Parser<A>,
-    F: Fn(A) -> B,
-{
-    bind(move |buf: &str| -> ParseRes<B> {
-        parser.parse(buf)
-            .map(|(b, out): (&str, A)| (b, functor(out)))
+// Decodes the character following a backslash in an escape sequence
+pub fn unescape (c: char) -> Option<char> {
+    match c {
+        'n'  => Some('\n'),
+        't'  => Some('\t'),
+        'r'  => Some('\r'),
+        '0'  => Some('\0'),
+        '\\' | '"' | '\'' => Some(c),
+        _    => None
+    }
+}
+
+
+/// Reads the body of a string delimited by `quote`, which `buf` must start
+/// with. Backslash escapes are decoded only if `escapes` is set.
+pub fn parse_quoted<'a> (buf: &'a str, quote: char, escapes: bool) -> ParseRes<'a, String> {
+    if !buf.starts_with(quote) {
+        return par_err_s(buf, format!("Literal '{}' not found", quote));
+    }
+
+    let mut out = String::new();
+    let mut iter = buf.char_indices().skip(1);
+    while let Some((i, c)) = iter.next() {
+        if c == quote {
+            return Ok((&buf[i + c.len_utf8()..], out));
+        }
+        if c == '\\' && escapes {
+            match iter.next().and_then(|(_, c)| unescape(c)) {
+                Some(c) => out.push(c),
+                None    => return par_err(&buf[i..], "invalid escape sequence"),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    par_err_s(buf, format!("unterminated string, missing '{}'", quote))
+}
+
+
+// Double quoted string literal, with backslash escapes
+pub fn parse_string<'a> (buf: &'a str) -> ParseRes<'a, String> {
+    parse_quoted(buf, '"', true)
+}
+
+/// String in either single or double quotes, closed by the quote it opened
+/// with. Backslash escapes are decoded.
+pub fn quoted () -> impl Parser<String> {
+    bind(|buf: &str| match buf.chars().next() {
+        Some(q @ ('"' | '\'')) => parse_quoted(buf, q, true),
+        _ => par_err(buf, "expected quoted string"),
     })
 }
 
-const KEYWORDS: [&str; 4] = [
-    "true",
-    "false",
-    "if",
-    "eval"
-];
+/// Like `parse_string`, but borrows the body from `buf` unless it has escapes
+/// to decode. Since `Parser` outputs cannot borrow the input, this is a plain
+/// function rather than a parser; call it directly.
+pub fn parse_string_cow<'a> (buf: &'a str) -> ParseRes<'a, Cow<'a, str>> {
+    let buf = skip_whitespace(buf);
+    let body = buf.strip_prefix('"').unwrap_or("");
+    match body.find(['"', '\\']) {
+        Some(i) if buf.starts_with('"') && body[i..].starts_with('"') =>
+            Ok((&body[i + 1..], Cow::Borrowed(&body[..i]))),
+        _ => parse_string(buf).map(|(rest, s)| (rest, Cow::Owned(s))),
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Segment<T> {
+    Text(String),
+    Expr(T),
+}
+
+/// Double-quoted template such as `"hi ${name}!"`, split into text and the
+/// `${...}` expressions parsed by `expr`, which must consume their contents.
+/// Braces may nest within an expression; `\$` is a literal `$`.
+pub fn interpolated<T, P> (expr: P) -> impl Parser<Vec<Segment<T>>>
+where
+    P: Parser<T>
+{
+    bind(move |buf: &str| {
+        if !buf.starts_with('"') {
+            return par_err(buf, "Literal '\"' not found");
+        }
+
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut i = 1;
+        while let Some(c) = buf[i..].chars().next() {
+            match c {
+                '"' => {
+                    if !text.is_empty() { segments.push(Segment::Text(text)) }
+                    return Ok((&buf[i + 1..], segments));
+                },
+                '\\' => match buf[i + 1..].chars().next() {
+                    Some('$') => { text.push('$'); i += 2 },
+                    Some(e) if unescape(e).is_some() => { text.push(unescape(e).unwrap()); i += 1 + e.len_utf8() },
+                    _ => return par_err(&buf[i..], "invalid escape sequence"),
+                },
+                '$' if buf[i + 1..].starts_with('{') => {
+                    let close = match matching_close(&buf[i + 1..], '{', '}') {
+                        Some(j) => i + 1 + j,
+                        None    => return par_err(&buf[i..], "unbalanced '${', missing '}'"),
+                    };
+                    let (rest, v) = expr.parse(&buf[i + 2..close])?;
+                    if !rest.trim().is_empty() {
+                        return par_err(rest, "unexpected input in interpolation");
+                    }
+                    if !text.is_empty() { segments.push(Segment::Text(std::mem::take(&mut text))) }
+                    segments.push(Segment::Expr(v));
+                    i = close + 1;
+                },
+                c => { text.push(c); i += c.len_utf8() },
+            }
+        }
+        par_err(buf, "unterminated string, missing '\"'")
+    })
+}
+
+
+// Whether `c` may appear within an identifier
+pub fn is_ident_char (c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+
+// Maximal run of identifier characters
+pub fn word<'a> (buf: &'a str) -> ParseRes<'a, String> {
+    parse_tok_with_rule(is_ident_char).parse(buf)
+}
+
+
+/// `lit`, only as a whole word: not run on into an identifier on either side.
+/// The preceding side is read from `with_origin`, so this fails outside of one.
+pub fn word_literal (lit: &str) -> impl Parser<String> {
+    let lit = raw_literal(lit);
+    bind(move |buf: &str| {
+        if origin_preceding_char(buf, "word_literal")?.is_some_and(is_ident_char) {
+            return par_err(buf, "literal starts within a word");
+        }
+        let (rest, s) = lit.parse(buf)?;
+        if rest.starts_with(is_ident_char) {
+            return par_err(rest, "literal runs on into a word");
+        }
+        Ok((rest, s))
+    })
+}
+
+/// Zero-width assertion that the position lies between an identifier character
+/// and anything else, like regex `\b`. The preceding side is read from
+/// `with_origin`, so this fails outside of one.
+pub fn word_boundary () -> impl Parser<()> {
+    raw(|buf: &str| {
+        let before = origin_preceding_char(buf, "word_boundary")?.is_some_and(is_ident_char);
+        let after = buf.chars().next().is_some_and(is_ident_char);
+        if before != after {
+            Ok((buf, ()))
+        } else {
+            par_err(buf, "expected word boundary")
+        }
+    })
+}
+
+
+pub fn parse_identifier<'a> (buf: &'a str) -> ParseRes<'a, String> {
+    let (buf, tok) = parse_tok_with_rule(is_ident_char).parse(buf)?;
+
+    if tok.chars().next().unwrap().is_ascii_digit() { return par_err(buf, "identifier cannot start with digit") }
+    if KEYWORDS.contains(&tok.as_str())             { return par_err(buf, "found keyword, cannot be used as identifier") }
+
+    Ok((buf, tok))
+}
+
+
+pub fn map<'a, A, B, P, F> (parser: P, functor: F) -> impl Parser<B> 
+where 
+    P: Parser<A>,
+    F: Fn(A) -> B,
+{
+    bind(move |buf: &str| -> ParseRes<B> {
+        parser.parse(buf)
+            .map(|(b, out): (&str, A)| (b, functor(out)))
+    })
+}
+
+// Runs `p`, also returning the text it matched
+pub fn with_recognized<T, P> (p: P) -> impl Parser<(T, String)>
+where
+    P: Parser<T>
+{
+    bind(move |buf: &str| {
+        p.parse(buf)
+            .map(|(b, out)| (b, (out, buf[..buf.len() - b.len()].to_owned())))
+    })
+}
+
+// Like `map`, also passing how many bytes `parser` consumed
+pub fn map_consumed<A, B, P, F> (parser: P, functor: F) -> impl Parser<B> 
+where 
+    P: Parser<A>,
+    F: Fn(A, usize) -> B,
+{
+    bind(move |buf: &str| -> ParseRes<B> {
+        parser.parse(buf)
+            .map(|(b, out): (&str, A)| (b, functor(out, buf.len() - b.len())))
+    })
+}
+
+/// Zero or more of `p`, as the slices of `buf` each match consumed, without
+/// copying them. Since `Parser` outputs cannot borrow the input, this is a
+/// plain function rather than a parser; call it directly.
+pub fn many_ref<'a, T, P> (p: P, buf: &'a str) -> ParseRes<'a, Vec<&'a str>>
+where
+    P: Parser<T>
+{
+    let mut v = vec![];
+    let mut buf_out = buf;
+    loop {
+        let start = skip_whitespace(buf_out);
+        let rest = match p.parse(start) {
+            Ok((rest, _))          => rest,
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(_)                 => break,
+        };
+        // A match that consumes nothing would repeat forever
+        if rest.len() == start.len() { break }
+        v.push(&start[..start.len() - rest.len()]);
+        buf_out = rest;
+    }
+    Ok((buf_out, v))
+}
+
+thread_local! {
+    // Inputs registered by `with_origin`, innermost last, with their addresses
+    // and the id of the run that registered them
+    static ORIGINS: RefCell<Vec<(usize, String, usize)>> = const { RefCell::new(vec![]) };
+    static ORIGIN_RUNS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Registers the buffer `p` is run on as the original input, for the duration
+/// of the parse, so parsers deeper in may look behind their position with
+/// `preceding_char`. Run the top-level parser through this. The buffer is
+/// copied on every run, so avoid nesting it inside rules run many times.
+pub fn with_origin<T, P> (p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    raw(move |buf: &str| {
+        let run = ORIGIN_RUNS.with(|r| r.replace(r.get() + 1));
+        ORIGINS.with(|o| o.borrow_mut().push((buf.as_ptr() as usize, buf.to_owned(), run)));
+        let res = p.parse(buf);
+        ORIGINS.with(|o| o.borrow_mut().pop());
+        res
+    })
+}
+
+/// The character before `buf` in the innermost `with_origin` input containing
+/// it. `None` at the start of that input, or if `buf` is not within one.
+pub fn preceding_char (buf: &str) -> Option<char> {
+    let ptr = buf.as_ptr() as usize;
+    ORIGINS.with(|o| {
+        o.borrow().iter().rev()
+            .find(|(base, s, _)| (*base..=base + s.len()).contains(&ptr))
+            .and_then(|(base, s, _)| s[..ptr - base].chars().next_back())
+    })
+}
+
+// `preceding_char`, failing for `name` if `buf` is not within a `with_origin`
+// input, where it could not tell the start of input from anywhere else
+fn origin_preceding_char<'a> (buf: &'a str, name: &str) -> Result<Option<char>, ParseErr<'a>> {
+    match origin_offset(buf) {
+        Some(_) => Ok(preceding_char(buf)),
+        None    => Err(ParseErr::new(format!("{} used outside of with_origin", name), buf)),
+    }
+}
+
+/// Offset of `buf` into the innermost `with_origin` input containing it
+pub fn origin_offset (buf: &str) -> Option<usize> {
+    origin_position(buf).map(|(_, offset)| offset)
+}
+
+// The run id of the innermost `with_origin` input containing `buf`, and the
+// offset into it. Ids are never reused, unlike addresses.
+fn origin_position (buf: &str) -> Option<(usize, usize)> {
+    let ptr = buf.as_ptr() as usize;
+    ORIGINS.with(|o| {
+        o.borrow().iter().rev()
+            .find(|(base, s, _)| (*base..=base + s.len()).contains(&ptr))
+            .map(|(base, _, run)| (*run, ptr - base))
+    })
+}
+
+/// `p`, only at the start of a line: at the start of the `with_origin` input
+/// or just after a line ending, as `line_ending` takes them. Whitespace is not
+/// skipped first, and this fails outside of `with_origin`.
+pub fn at_line_start<T, P> (p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    raw(move |buf: &str| match origin_preceding_char(buf, "at_line_start")? {
+        None | Some('\n') => p.parse(buf),
+        Some('\r') if !buf.starts_with('\n') => p.parse(buf),
+        Some(_) => par_err(buf, "expected start of line"),
+    })
+}
+
+/// Skips a `#!` line, including its line ending, at the very start of the
+/// `with_origin` input only. Anywhere else, or without one, consumes nothing.
+pub fn skip_shebang () -> impl Parser<()> {
+    raw(|buf: &str| {
+        if origin_offset(buf) != Some(0) || !buf.starts_with("#!") {
+            return Ok((buf, ()));
+        }
+        let end = buf.find('\n').map_or(buf.len(), |i| i + 1);
+        Ok((&buf[end..], ()))
+    })
+}
+
+/// Ties the knot for recursive grammars. `f` receives a handle to the parser
+/// being built and returns its definition; the handle may be used anywhere in
+/// that definition. Both `T` and the builder's captures must outlive `'a`.
+pub fn fix<'a, T, F> (f: F) -> BoxedParser<'a, T>
+where
+    T: 'a,
+    F: Fn(BoxedParser<'a, T>) -> BoxedParser<'a, T>
+{
+    let cell: Rc<OnceCell<BoxedParser<'a, T>>> = Rc::new(OnceCell::new());
+
+    // The handle only holds a weak reference, so the parser does not own itself
+    let weak = Rc::downgrade(&cell);
+    let handle = BoxedParser::new(bind(move |buf: &str| {
+        weak.upgrade()
+            .expect("Unexpected: recursive parser used after being dropped")
+            .get()
+            .expect("Unexpected: recursive parser used before being defined")
+            .parse(buf)
+    }));
+
+    if cell.set(f(handle)).is_err() {
+        panic!("Unexpected: recursive parser defined twice");
+    }
+    BoxedParser::new(bind(move |buf: &str| cell.get().unwrap().parse(buf)))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Ternary<C, B> {
+    Value(B),
+    Cond(C, Box<Ternary<C, B>>, Box<Ternary<C, B>>),
+}
+
+/// Conditional expressions, `cond <then_sep> t <else_sep> t`, where either
+/// branch may itself be a conditional. Chains nest to the right, so
+/// `a ? b : c ? d : e` is `a ? b : (c ? d : e)`.
+pub fn ternary<'a, C, B, PC, PB> (cond: PC, then_sep: &str, else_sep: &str, branch: PB) -> BoxedParser<'a, Ternary<C, B>>
+where
+    C: 'a,
+    B: 'a,
+    PC: Parser<C> + 'a,
+    PB: Parser<B> + 'a,
+{
+    let cond = Rc::new(BoxedParser::new(attempt(suffix(then_sep, cond))));
+    let branch = Rc::new(BoxedParser::new(branch));
+    let else_sep = else_sep.to_owned();
+
+    fix(move |t| {
+        let (cond, branch, else_sep) = (cond.clone(), branch.clone(), else_sep.clone());
+        let t = Rc::new(t);
+        let t2 = t.clone();
+
+        let conditional = BoxedParser::new(bind(move |buf: &str| cond.parse(buf)))
+            .and(bind(move |buf: &str| t.parse(buf)))
+            .suffix(&else_sep)
+            .and(bind(move |buf: &str| t2.parse(buf)))
+            .map(|((c, a), b)| Ternary::Cond(c, Box::new(a), Box::new(b)));
+
+        conditional.or(map(bind(move |buf: &str| branch.parse(buf)), Ternary::Value))
+    })
+}
+
+/// Fails once `p` is re-entered more than `limit` times without returning.
+/// Wrap the recursive entry point of a grammar (eg. inside `fix`) with this to
+/// reject deeply nested input instead of overflowing the stack.
+pub fn max_depth<T, P> (limit: usize, p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    let depth = Cell::new(0usize);
+    bind(move |buf: &str| {
+        if depth.get() >= limit {
+            return par_err(buf, "max nesting depth exceeded");
+        }
+        depth.set(depth.get() + 1);
+        let res = p.parse(buf);
+        depth.set(depth.get() - 1);
+        res
+    })
+}
+
+// Fails if `p` consumes fewer than `min` bytes, not counting skipped whitespace
+pub fn must_consume<T, P> (min: usize, p: P) -> impl Parser<T>
+where
+    P: Parser<T>
+{
+    bind(move |buf: &str| {
+        let (rest, o) = p.parse(buf)?;
+        let consumed = buf.len() - rest.len();
+        if consumed < min {
+            return par_err_s(buf, format!("consumed {1} of the required {0} bytes", min, consumed));
+        }
+        Ok((rest, o))
+    })
+}
+
+// Fails, without consuming, if the output of `p` does not satisfy `pred`
+pub fn verify<T, P, F> (p: P, pred: F) -> impl Parser<T>
+where
+    P: Parser<T>,
+    F: Fn(&T) -> bool,
+{
+    bind(move |buf: &str| match p.parse(buf)? {
+        (_, o) if !pred(&o) => par_err(buf, "verification failed"),
+        ok => Ok(ok)
+    })
+}
+
+// Like `verify`, for a checksum over the output of `p`, eg. `formats::luhn`
+pub fn checksummed<T, P, F> (p: P, validate: F) -> impl Parser<T>
+where
+    P: Parser<T>,
+    F: Fn(&T) -> bool,
+{
+    bind(move |buf: &str| match p.parse(buf)? {
+        (_, o) if !validate(&o) => par_err(buf, "checksum failed"),
+        ok => Ok(ok)
+    })
+}
+
+// Like `map`, but the functor may reject the output with an error message
+pub fn try_map<A, B, P, F> (parser: P, functor: F) -> impl Parser<B> 
+where 
+    P: Parser<A>,
+    F: Fn(A) -> Result<B, String>,
+{
+    bind(move |buf: &str| -> ParseRes<B> {
+        let (b, out) = parser.parse(buf)?;
+        match functor(out) {
+            Ok(o)  => Ok((b, o)),
+            Err(s) => par_err_s(buf, s),
+        }
+    })
+}
+
+// Outcome of a parse, stored as offsets from where it started
+type Memo<T> = Result<(usize, T), (usize, ParseErr<'static>)>;
+
+/// Memo table for `cached`, keyed on rule id and offset into the `with_origin`
+/// input. Entries are kept for every `with_origin` run still in progress, so a
+/// nested `with_origin` does not wipe its parent's; those of finished runs are
+/// dropped once a later run uses the cache.
+pub struct Cache<T> {
+    memo:   RefCell<HashMap<(usize, usize, usize), Memo<T>>>,
+    latest: Cell<Option<usize>>,
+}
+impl<T: Clone> Cache<T> {
+    pub fn new () -> Self {
+        Self { memo: RefCell::new(HashMap::new()), latest: Cell::new(None) }
+    }
+
+    pub fn clear (&self) {
+        self.memo.borrow_mut().clear();
+        self.latest.set(None);
+    }
+
+    // Drops the entries of runs that have finished, on first use in `run`
+    fn enter (&self, run: usize) {
+        if self.latest.get().is_some_and(|latest| run <= latest) {
+            return;
+        }
+        self.latest.set(Some(run));
+        ORIGINS.with(|o| {
+            let live = o.borrow();
+            self.memo.borrow_mut().retain(|(r, _, _), _| live.iter().any(|(_, _, id)| id == r));
+        });
+    }
+}
+
+/// Memoizes `p` in `cache`, so it runs at most once per position. Parsers
+/// sharing a cache must use distinct `rule` ids unless they are the same rule.
+/// Positions are offsets into the enclosing `with_origin` input, so this fails
+/// outside of one, like other parsers depending on the position.
+pub fn cached<'c, T, P> (cache: &'c Cache<T>, rule: usize, p: P) -> impl Parser<T> + 'c
+where
+    T: Clone,
+    P: Parser<T> + 'c,
+{
+    bind(move |buf: &str| {
+        let Some((run, offset)) = origin_position(buf) else {
+            return par_err(buf, "cached used outside of with_origin");
+        };
+        cache.enter(run);
+        let key = (run, rule, offset);
+
+        let hit = cache.memo.borrow().get(&key).cloned();
+        let memo = match hit {
+            Some(memo) => memo,
+            None => {
+                let memo = match p.parse(buf) {
+                    Ok((rest, o)) => Ok((buf.len() - rest.len(), o)),
+                    Err(e)        => Err((e.offset(buf), e.with_ptr(""))),
+                };
+                cache.memo.borrow_mut().insert(key, memo.clone());
+                memo
+            }
+        };
+
+        match memo {
+            Ok((len, o))              => Ok((&buf[len..], o)),
+            Err((offset, e))          => Err(e.with_ptr(&buf[offset..])),
+        }
+    })
+}
+
+const KEYWORDS: [&str; 4] = [
+    "true",
+    "false",
+    "if",
+    "eval"
+];
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_recognized () {
+        let p = with_recognized(parse_number);
+        assert!(p.parse("3.25e0 rest").unwrap() == (" rest", (3.25, "3.25e0".to_owned())));
+        assert!(p.parse("2.5E-2").unwrap() == ("", (0.025, "2.5E-2".to_owned())));
+
+        // Not an exponent without digits
+        assert!(p.parse("2else").unwrap() == ("else", (2.0, "2".to_owned())));
+    }
+
+    #[test]
+    fn test_many_ref () {
+        let input = "a bb  c;";
+        let (rest, words) = many_ref(parse_identifier, input).unwrap();
+        assert!(rest == ";" && words == vec!["a", "bb", "c"]);
+
+        // The words borrow from the input itself
+        let range = input.as_bytes().as_ptr_range();
+        assert!(words.iter().all(|w| range.contains(&w.as_ptr())));
+        assert!(many_ref(parse_int, "x") == Ok(("x", vec![])));
+    }
+
+    #[test]
+    fn test_map_consumed () {
+        #[derive(Debug, PartialEq)]
+        struct Token { value: f64, width: usize }
+
+        let p = map_consumed(parse_number, |value, width| Token { value, width });
+        assert!(p.parse("  12.50+1").unwrap() == ("+1", Token { value: 12.5, width: 5 }));
+    }
+
+    #[test]
+    fn test_apply () {
+        // Without `apply`, this closure's return type is not tied to its argument
+        let double = apply(|buf: &str| parse_number(buf).map(|(b, n)| (b, n * 2.0)));
+
+        let p = and(double, prefix(",", zero_or_more(parse_identifier)));
+        assert!(p.parse("21, a b").unwrap() == ("", (42.0, vec!["a".to_owned(), "b".to_owned()])));
+    }
+
+    #[test]
+    fn test_at_line_start () {
+        let heading = at_line_start(raw_literal("#"));
+        let p = with_origin(bind(|buf: &str| {
+            let found: Vec<bool> = [0, 6, 10].iter().map(|&i| heading.parse(&buf[i..]).is_ok()).collect();
+            Ok((buf, found))
+        }));
+        assert!(p.parse("# a\nb # c\n# d") == Ok(("# a\nb # c\n# d", vec![true, false, true])));
+
+        // A lone "\r" ends a line, but not the "\r" of a "\r\n"
+        let p = with_origin(bind(|buf: &str| {
+            let found: Vec<bool> = [4, 5, 7].iter().map(|&i| at_line_start(raw_literal("")).parse(&buf[i..]).is_ok()).collect();
+            Ok((buf, found))
+        }));
+        assert!(p.parse("# a\r\nb\r#").unwrap().1 == vec![false, true, true]);
+
+        let err = heading.parse("# a").unwrap_err();
+        assert!(err.msg == "at_line_start used outside of with_origin");
+    }
+
+    #[test]
+    fn test_skip_shebang () {
+        let p = with_origin(skip_shebang());
+        assert!(p.parse("#!/usr/bin/env foo\n#x\nrest") == Ok(("#x\nrest", ())));
+        assert!(p.parse("#!only") == Ok(("", ())));
+        assert!(p.parse("# comment\nrest") == Ok(("# comment\nrest", ())));
+        assert!(p.parse(" #!x") == Ok((" #!x", ())));
+
+        // Not past the start of the input
+        let p = with_origin(and(raw_literal("a\n"), skip_shebang()));
+        assert!(p.parse("a\n#!x\n") == Ok(("#!x\n", ("a\n".to_owned(), ()))));
+
+        // Nor anywhere without an origin, as the start can't be told apart
+        let p = and(raw_literal("a\n"), skip_shebang());
+        assert!(p.parse("a\n#!x\n") == Ok(("#!x\n", ("a\n".to_owned(), ()))));
+        assert!(skip_shebang().parse("#!x\nrest") == Ok(("#!x\nrest", ())));
+    }
+
+    #[test]
+    fn test_fix () {
+        // tree := '(' tree* ')'   -> returns the nesting depth
+        let tree = fix(|tree| 
+            BoxedParser::new(prefix("(", zero_or_more(tree)))
+                .suffix(")")
+                .map(|v| 1 + v.into_iter().max().unwrap_or(0))
+        );
+
+        assert!(tree.test("()") == 1);
+        assert!(tree.test("(()(()))") == 3);
+        assert!(tree.test("( ( ) ( ( ( ) ) ) )") == 4);
+        assert!(tree.parse("(()").is_err());
+    }
+
+    #[test]
+    fn test_ternary () {
+        use Ternary::*;
+        let t = ternary(parse_identifier, "?", ":", parse_number);
+
+        assert!(t.test("1") == Value(1.0));
+
+        let expected = Cond(
+            "x".to_owned(),
+            Box::new(Value(1.0)),
+            Box::new(Cond("y".to_owned(), Box::new(Value(2.0)), Box::new(Value(3.0))))
+        );
+        assert!(t.test("x ? 1 : y ? 2 : 3") == expected);
+
+        let expected = Cond(
+            "x".to_owned(),
+            Box::new(Cond("y".to_owned(), Box::new(Value(1.0)), Box::new(Value(2.0)))),
+            Box::new(Value(3.0))
+        );
+        assert!(t.test("x ? y ? 1 : 2 : 3") == expected);
+        assert!(t.parse("x ? 1").is_err());
+    }
+
+    #[test]
+    fn test_max_depth () {
+        let parens = fix(|parens| 
+            BoxedParser::new(max_depth(3, 
+                surround("(", ")", option(parens))
+            )).map(|_| ())
+        );
+
+        assert!(parens.parse("((()))").is_ok());
+        assert!(parens.parse("(((())))").is_err());
+
+        // Deep input is rejected well before the stack runs out
+        let deep = "(".repeat(100_000) + &")".repeat(100_000);
+        assert!(parens.parse(&deep).is_err());
+
+        let err = max_depth(0, parse_literal("(")).parse("(").unwrap_err();
+        assert!(err == ParseErr::new("max nesting depth exceeded".to_owned(), "("));
+    }
+
+    #[test]
+    fn test_option_spanned () {
+        let p = with_origin(and(parse_identifier, option_spanned(prefix(":", parse_identifier))));
+        assert!(p.parse("x : int") == Ok(("", ("x".to_owned(), (Some("int".to_owned()), 2)))));
+        assert!(p.parse("value  = 1") == Ok(("= 1", ("value".to_owned(), (None, 7)))));
+        assert!(with_origin(option_spanned(parse_int)).parse("  a") == Ok(("a", (None, 2))));
+
+        // Without an origin there is no offset to give
+        let err = and(parse_identifier, option_spanned(parse_int)).parse("abc x").unwrap_err();
+        assert!(err.msg == "option_spanned used outside of with_origin");
+    }
+
+    #[test]
+    fn test_or_else_with () {
+        #[derive(Debug, PartialEq)]
+        enum Expr { Num(f64), Error(String) }
+        let expr = or_else_with(map(parse_number, Expr::Num), |e| Expr::Error(e.msg.clone()));
+
+        assert!(expr.parse("1.5;") == Ok((";", Expr::Num(1.5))));
+        let (rest, e) = expr.parse(" ?;").unwrap();
+        assert!(rest == "?;" && matches!(e, Expr::Error(_)));
+
+        // Not past a commit
+        let expr = or_else_with(map(commit_after(parse_literal("-"), parse_number), |(_, n)| Expr::Num(-n)), |e| Expr::Error(e.msg.clone()));
+        assert!(matches!(expr.parse("x"), Ok(("x", Expr::Error(_)))));
+        assert!(expr.parse("- x").unwrap_err().is_fatal());
+    }
+
+    #[test]
+    fn test_commit_after () {
+        let p = or(commit_after(parse_literal("let"), parse_identifier), map(parse_identifier, |s| (String::new(), s)));
+        assert!(p.parse("let x") == Ok(("", ("let".to_owned(), "x".to_owned()))));
+        assert!(p.parse("x") == Ok(("", (String::new(), "x".to_owned()))));
+        let err = p.parse("let 1").unwrap_err();
+        assert!(err.is_fatal() && err.msg == "identifier cannot start with digit");
+    }
+
+    #[test]
+    fn test_longest () {
+        let ops = || vec![BoxedParser::new(parse_literal("=")), BoxedParser::new(parse_literal("=="))];
+        assert!(longest(ops()).parse("== b") == Ok((" b", "==".to_owned())));
+        assert!(or(parse_literal("="), parse_literal("==")).parse("== b") == Ok(("= b", "=".to_owned())));
+
+        let err = longest(ops()).parse("!=").unwrap_err();
+        assert!(err.expected_labels() == vec!["'='", "'=='"]);
+        assert!(longest::<String>(vec![]).parse("x").is_err());
+    }
+
+    #[test]
+    fn test_ambiguity_check () {
+        let alts = || vec![
+            BoxedParser::new(parse_literal("in")),
+            BoxedParser::new(parse_identifier),
+            BoxedParser::new(parse_literal("int")),
+        ];
+        let err = ambiguity_check(alts()).parse("int x").unwrap_err();
+        assert!(err.msg == "ambiguous alternatives 1, 2 match the same input");
+
+        // Matches of different lengths are not ambiguous
+        assert!(ambiguity_check(alts()).parse("inside") == Ok(("side", "in".to_owned())));
+        assert!(ambiguity_check(alts()).parse("in x").unwrap_err().msg == "ambiguous alternatives 0, 1 match the same input");
+        assert!(ambiguity_check(alts()).parse("1").is_err());
+    }
+
+    #[test]
+    fn test_same () {
+        let tag = || same(surround("<", ">", parse_identifier), skip(field("<")), surround("</", ">", parse_identifier));
+        assert!(tag().parse("<b>x</b> y") == Ok((" y", "b".to_owned())));
+
+        let err = tag().parse("<b>x</i>").unwrap_err();
+        assert!(err.msg == "does not match the value it pairs with" && err.ptr == "</i>");
+        assert!(tag().parse("<b>x").is_err());
+    }
+
+    #[test]
+    fn test_attempt () {
+        let ab = || prefix("a", cut(parse_literal("b")));
+
+        // Once past the cut, `or` does not try the second branch
+        let err = or(ab(), parse_literal("ac")).parse("ac").unwrap_err();
+        assert!(err.is_fatal() && err.ptr == "c");
+        assert!(option(ab()).parse("ac").is_err());
+
+        let (buf, s) = or(attempt(ab()), parse_literal("ac")).parse("ac!").unwrap();
+        assert!(s == "ac" && buf == "!");
+
+        let err = attempt(ab()).parse("ac").unwrap_err();
+        assert!(!err.is_fatal() && err.ptr == "ac");
+    }
+
+    #[test]
+    fn test_interleaved () {
+        let p = interleaved(parse_tok_with_rule(char::is_alphabetic), parse_number);
+
+        let (buf, (a, b)) = p.parse("a1b2c3").unwrap();
+        assert!(a == vec!["a", "b", "c"] && b == vec![1.0, 2.0, 3.0] && buf.is_empty());
+
+        let (buf, (a, b)) = p.parse("a1b;").unwrap();
+        assert!(a == vec!["a", "b"] && b == vec![1.0] && buf == ";");
+    }
+
+    #[test]
+    fn test_many_spanned () {
+        let (rest, v) = many_spanned(parse_identifier).parse("a bb  c;").unwrap();
+        let spans: Vec<_> = v.iter().map(|s| (s.value.as_str(), s.start, s.end)).collect();
+        assert!(rest == ";" && spans == vec![("a", 0, 1), ("bb", 2, 4), ("c", 6, 7)]);
+
+        let p = with_origin(and(parse_literal("x ="), many_spanned(parse_int)));
+        let (_, (_, v)) = p.parse("x = 1 23").unwrap();
+        assert!(v == vec![Spanned { value: 1, start: 4, end: 5 }, Spanned { value: 23, start: 6, end: 8 }]);
+    }
+
+    #[test]
+    fn test_fill () {
+        let mut out = Vec::with_capacity(5);
+        {
+            let p = fill(parse_int, &mut out);
+            assert!(p.parse("1 2 3 4 5;") == Ok((";", ())));
+        }
+        assert!(out == vec![1, 2, 3, 4, 5] && out.capacity() == 5);
+
+        let p = fill(parse_int, &mut out);
+        assert!(p.parse(" 6 x") == Ok((" x", ())));
+        drop(p);
+        assert!(out.len() == 6);
+
+        // Re-entered through a nested list, each list pushing a 0 after it
+        let mut out = vec![];
+        {
+            let slot = Cell::new(Some(&mut out));
+            let p = fix(|list| BoxedParser::new(fill(or(parse_int, map(surround("(", ")", list), |_| 0)), slot.take().unwrap())));
+            assert!(p.parse("1 (2 3) 4") == Ok(("", ())));
+        }
+        assert!(out == vec![1, 2, 3, 0, 4]);
+    }
+
+    #[test]
+    fn test_repeat () {
+        let spec = |s: &str| s.parse::<RepeatSpec>().unwrap();
+        assert!(spec("{5}") == RepeatSpec::Exactly(5) && spec("{3,}") == RepeatSpec::AtLeast(3));
+        assert!(spec("{,2}") == RepeatSpec::AtMost(2) && spec("{2, 4}") == RepeatSpec::Between(2, 4));
+        assert!("{4,2}".parse::<RepeatSpec>().is_err() && "2,4".parse::<RepeatSpec>().is_err());
+        assert!("{a}".parse::<RepeatSpec>().is_err());
+
+        let input = "1 2 3";
+        assert!(repeat(parse_int, spec("{3}")).parse(input) == Ok(("", vec![1, 2, 3])));
+        assert!(repeat(parse_int, spec("{2}")).parse(input) == Ok((" 3", vec![1, 2])));
+        assert!(repeat(parse_int, spec("{2}")).parse(input) == count(parse_int, 2).parse(input));
+        assert!(repeat(parse_int, spec("{2,}")).parse(input) == Ok(("", vec![1, 2, 3])));
+        let err = repeat(parse_int, spec("{4,}")).parse(input).unwrap_err();
+        assert!(err.msg == "3 of pattern found in 'repeat', expected at least 4");
+        assert!(repeat(parse_int, spec("{,3}")).parse(input) == Ok(("", vec![1, 2, 3])));
+        assert!(repeat(parse_int, spec("{,1}")).parse(input) == Ok((" 2 3", vec![1])));
+        assert!(repeat(parse_int, spec("{2,4}")).parse(input) == Ok(("", vec![1, 2, 3])));
+        assert!(repeat(parse_int, spec("{1,2}")).parse(input) == Ok((" 3", vec![1, 2])));
+        assert!(repeat(parse_int, spec("{4,5}")).parse(input).is_err());
+    }
+
+    #[test]
+    fn test_count () {
+        assert!(count(digit(), 3).parse("1234").unwrap() == ("4", vec![1, 2, 3]));
+        assert!(count(digit(), 3).parse("12").is_err());
+        assert!(count(digit(), 2).parse("1 2").is_err());
+        assert!(count(parse_number, 2).parse("1 2").unwrap() == ("", vec![1.0, 2.0]));
+
+        let err = count(digit(), 3).parse("12").unwrap_err();
+        assert!(err.msg == "2 of pattern found in 'count', expected 3" && err.ptr.is_empty());
+    }
+
+    #[test]
+    fn test_verify () {
+        let even = verify(parse_number, |n| n % 2.0 == 0.0);
+        assert!(even.parse("4").unwrap() == ("", 4.0));
+
+        let err = even.parse(" 3").unwrap_err();
+        assert!(err.msg == "verification failed" && err.ptr == "3");
+        assert!(raw_literal("-").parse(" -").is_err());
+    }
+
+    #[test]
+    fn test_sep_by () {
+        let p = sep_by(parse_number, ",");
+
+        assert!(p.parse("1, 2 ,3;").unwrap() == (";", vec![1.0, 2.0, 3.0]));
+        assert!(p.parse("1,2,").unwrap() == (",", vec![1.0, 2.0]));
+        assert!(p.parse(";").unwrap() == (";", vec![]));
+        assert!(sep_by1(parse_number, ",").parse(";").is_err());
+    }
+
+    #[test]
+    fn test_sep_by_keeping () {
+        let p = sep_by_keeping(parse_int, or(parse_literal("+"), parse_literal("-")));
+        let (rest, (nums, ops)) = p.parse("1 + 2 - 3 +").unwrap();
+        assert!(rest == " +" && nums == vec![1, 2, 3] && ops == vec!["+", "-"]);
+
+        assert!(p.parse("x") == Ok(("x", (vec![], vec![]))));
+        assert!(p.parse("7") == Ok(("", (vec![7], vec![]))));
+    }
+
+    #[test]
+    fn test_comparison_chain () {
+        let ops = || literal_map(&[("<", "<"), ("<=", "<="), (">", ">")]);
+        let chain = comparison_chain(parse_int, ops());
+        assert!(chain.parse("1 < 2 < 3") == Ok(("", vec![(1, "<", 2), (2, "<", 3)])));
+        assert!(chain.parse("1 <= 5 > 2;") == Ok((";", vec![(1, "<=", 5), (5, ">", 2)])));
+        assert!(chain.parse("1").is_err());
+    }
+
+    #[test]
+    fn test_grid () {
+        let table = grid(parse_int, "|", "\n");
+        assert!(table.parse("1 | 2 | 3\n4 | 5 | 6") == Ok(("", vec![vec![1, 2, 3], vec![4, 5, 6]])));
+        assert!(table.parse("1|2\n3|4\n;") == Ok((";", vec![vec![1, 2], vec![3, 4]])));
+
+        let err = table.parse("1 | 2 | 3\n4 | 5\n").unwrap_err();
+        assert!(err.msg == "row 2 has 2 columns, expected 3" && err.ptr == "4 | 5\n");
+        assert!(table.parse("x").is_err());
+    }
+
+    #[test]
+    fn test_capped () {
+        let p = zero_or_more_capped(parse_literal("a"), 3);
+        assert!(p.parse("aaab").unwrap() == ("b", vec!["a".to_owned(); 3]));
+
+        let err = p.parse("aaaab").unwrap_err();
+        assert!(err.msg == "more than 3 of pattern found in 'zero_or_more_capped'" && err.ptr == "ab");
+
+        assert!(one_or_more_capped(parse_literal("a"), 3).parse("b").is_err());
+        assert!(one_or_more_capped(parse_literal("a"), 3).parse("aaaa").is_err());
+
+        let p = sep_by_capped(parse_number, ",", 2);
+        assert!(p.parse("1,2").unwrap() == ("", vec![1.0, 2.0]));
+        assert!(p.parse("1,2,3").is_err());
+    }
+
+    #[test]
+    fn test_bracketed_list () {
+        let list = bracketed_list("[", parse_number, "]");
+
+        assert!(list.parse("[1, 2, 3]").unwrap() == ("", vec![1.0, 2.0, 3.0]));
+        assert!(list.parse("[1,2,3,]").unwrap() == ("", vec![1.0, 2.0, 3.0]));
+        assert!(list.parse("[ ]").unwrap() == ("", vec![]));
+        assert!(list.parse("[]").unwrap() == ("", vec![]));
+
+        assert!(list.parse("[,]").is_err());
+        assert!(list.parse("[1 2]").is_err());
+    }
+
+    #[test]
+    fn test_arg_list () {
+        assert!(arg_list(parse_int).parse("(1, 2, 3)") == Ok(("", vec![1, 2, 3])));
+        assert!(arg_list(parse_int).parse("()") == Ok(("", vec![])));
+        assert!(arg_list(parse_int).parse("( 1 )") == Ok(("", vec![1])));
+        assert!(arg_list(parse_int).parse("(1, 2,)").is_err());
+    }
+
+    #[test]
+    fn test_skip () {
+        let (buf, _) = skip(parse_literal("#")).parse("#abc").unwrap();
+        assert!(buf == "abc");
+
+        // Like any combinator, leading whitespace is skipped first
+        assert!(skip(raw_literal("#")).parse(" \n#a") == Ok(("a", ())));
+        assert!(skip(line_ending()).parse("\nx").is_err());
+
+        let ws = || count_while(char::is_whitespace);
+
+        let (buf, _) = skip_many(ws()).parse("  \t\t x").unwrap();
+        assert!(buf == "x");
+        let (buf, _) = skip_many(parse_literal("ab")).parse("ababa").unwrap();
+        assert!(buf == "a");
+
+        // Zero-width matches do not count towards skip_many1
+        assert!(skip_many1(ws()).parse("x").is_err());
+        let (buf, _) = skip_many1(ws()).parse("\t x").unwrap();
+        assert!(buf == "x");
+    }
+
+    #[test]
+    fn test_cached () {
+        let runs = Cell::new(0);
+        let expensive = bind(|buf: &str| {
+            runs.set(runs.get() + 1);
+            parse_number(buf)
+        });
+
+        let cache = Cache::new();
+        let p = with_origin(or(
+            suffix("x", cached(&cache, 0, expensive)),
+            suffix("y", cached(&cache, 0, expensive))
+        ));
+
+        let (buf, n) = p.parse("12y").unwrap();
+        assert!(n == 12.0 && buf.is_empty());
+        assert!(runs.get() == 1);
+
+        // Failures are cached too
+        assert!(p.parse("z").is_err());
+        assert!(runs.get() == 2);
+
+        // A new input at the same address does not see the old entries
+        let mut input = String::from("12y");
+        assert!(p.parse(&input).unwrap().1 == 12.0);
+        input.replace_range(.., "34y");
+        assert!(p.parse(&input).unwrap().1 == 34.0);
+        assert!(runs.get() == 4);
+
+        let err = cached(&cache, 0, parse_number).parse("12").unwrap_err();
+        assert!(err.msg == "cached used outside of with_origin");
+
+        // A nested `with_origin` keeps the outer run's entries
+        runs.set(0);
+        let num = || cached(&cache, 0, expensive);
+        let p = with_origin(bind(|buf| {
+            let (_, a) = num().parse(buf)?;
+            let (_, b) = with_origin(num()).parse("7")?;
+            let (buf, c) = num().parse(buf)?;
+            Ok((buf, a + b + c))
+        }));
+        assert!(p.parse("12") == Ok(("", 31.0)));
+        assert!(runs.get() == 2);
+
+        // Finished runs' entries go once another run starts
+        assert!(p.parse("12") == Ok(("", 31.0)));
+        assert!(runs.get() == 4 && cache.memo.borrow().len() == 2);
+    }
+
+    #[test]
+    fn test_describe () {
+        assert!(parse_literal(")").describe() == "the literal ')'");
+        assert!(described("a number", parse_number).describe() == "a number");
+
+        let p = or(parse_literal("("), described("a number", map(parse_number, |n| n.to_string())));
+        assert!(p.describe() == "one of: the literal '(', a number");
+
+        let p = or(parse_literals(vec!["+", "-"]), or(parse_literal("*"), parse_literal("/")));
+        assert!(p.describe() == "one of: the literal '+', the literal '-', the literal '*', the literal '/'");
+        assert!(p.parse(" /").unwrap() == ("", "/".to_owned()));
+    }
+
+    #[test]
+    fn test_expected_labels () {
+        let p = or(
+            or(parse_literal("a"), parse_literals(vec!["b", "a"])),
+            or(parse_literal("b"), prefix("x", parse_literal("c")))
+        );
+
+        // Every alternative failed at the start
+        let err = p.parse("d").unwrap_err();
+        assert!(err.expected_labels() == vec!["'a'", "'b'", "'x'"]);
+
+        // Only the alternative that got further is reported
+        let err = p.parse("xd").unwrap_err();
+        assert!(err.expected_labels() == vec!["'c'"] && err.ptr == "d");
+    }
+
+    #[test]
+    fn test_count_while () {
+        let indent = count_while(|c| c == ' ' || c == '\t');
+
+        let (buf, (n, s)) = indent.parse("  \t x = 1").unwrap();
+        assert!(n == 4 && s == "  \t " && buf == "x = 1");
+
+        let (buf, (n, s)) = indent.parse("x").unwrap();
+        assert!(n == 0 && s.is_empty() && buf == "x");
+
+        // Counts characters, not bytes
+        let (buf, (n, s)) = count_while(|c| c == 'é').parse("ééa").unwrap();
+        assert!(n == 2 && s == "éé" && buf == "a");
+    }
+
+    #[test]
+    fn test_indentation () {
+        assert!(indentation(4).parse("x") == Ok(("x", 0)));
+        assert!(indentation(4).parse("  \tx") == Ok(("x", 6)));
+        assert!(indentation(8).parse("\t \t  y = 1") == Ok(("y = 1", 19)));
+        assert!(indentation(2).parse("   ") == Ok(("", 3)));
+    }
+
+    #[test]
+    fn test_field () {
+        let f = field(",;");
+
+        let (buf, s) = f.parse(" a b ,c").unwrap();
+        assert!(s == " a b " && buf == ",c");
+
+        let (buf, s) = f.parse("abc").unwrap();
+        assert!(s == "abc" && buf.is_empty());
+
+        let (buf, s) = f.parse(";abc").unwrap();
+        assert!(s.is_empty() && buf == ";abc");
+    }
+
+    #[test]
+    fn test_flags_of () {
+        #[derive(Debug, PartialEq, Clone, Copy)]
+        enum Perm { Read, Write, WriteAll }
+        let p = flags_of(vec![("read", Perm::Read), ("write", Perm::Write), ("writeall", Perm::WriteAll)], "|");
+
+        assert!(p.parse("read|write") == Ok(("", vec![Perm::Read, Perm::Write])));
+        assert!(p.parse("writeall | read;") == Ok((";", vec![Perm::WriteAll, Perm::Read])));
+        let err = p.parse("read|fly").unwrap_err();
+        assert!(err.msg == "unknown flag 'fly'" && err.ptr == "fly");
+
+        let err = p.parse(" fly|read").unwrap_err();
+        assert!(err.msg == "unknown flag 'fly'" && err.ptr == "fly|read");
+        assert!(err.expected_labels().contains(&"'writeall'"));
+    }
+
+    #[test]
+    fn test_from_end () {
+        let unit = from_end(parse_tok_with_rule(|c| c.is_ascii_alphabetic()));
+        let (prefix, u) = unit.parse("100px").unwrap();
+        assert!(prefix == "100" && u == "px");
+        assert!(parse_int(prefix) == Ok(("", 100)));
+
+        assert!(from_end(parse_literal(".tar.gz")).parse("a.tar.gz") == Ok(("a", ".tar.gz".to_owned())));
+        assert!(unit.parse("100").is_err());
+    }
+
+    #[test]
+    fn test_with_input_limit () {
+        let any = || zero_or_more(char_class("^"));
+        assert!(with_input_limit(8, any()).parse("abc") == Ok(("", vec!['a', 'b', 'c'])));
+        assert!(with_input_limit(4, zero_or_more(parse_int)).parse("1 2; 3") == Ok(("; 3", vec![1, 2])));
+
+        let long = "a".repeat(100);
+        let err = with_input_limit(8, any()).parse(&long).unwrap_err();
+        assert!(err.msg == "input exceeds the limit of 8 bytes");
+        assert!(with_input_limit(2, any()).parse("aé").is_err());
+
+        // Going past the limit fails, even part way through a match
+        assert!(with_input_limit(4, zero_or_more(parse_int)).parse("1 2 3").is_err());
+        assert!(with_input_limit(3, zero_or_more(parse_literal("ab"))).parse("ababab").is_err());
+        assert!(with_input_limit(3, parse_literal("abc")).parse("abcd") == Ok(("d", "abc".to_owned())));
+
+        // `p` is never shown more than one character past the limit
+        let seen = Cell::new(0);
+        let spy = raw(|buf: &str| {
+            seen.set(seen.get().max(buf.len()));
+            zero_or_more(char_class("^")).parse(buf)
+        });
+        assert!(with_input_limit(8, spy).parse(&long).is_err());
+        assert!(seen.get() == 9);
+        assert!(with_input_limit(1, any()).parse("éa").is_err());
+    }
+
+    #[test]
+    fn test_normalized_text () {
+        let text = normalized_text(|c| c == ';');
+        assert!(text.parse("  foo   bar \t baz  ") == Ok(("", "foo bar baz".to_owned())));
+        assert!(text.parse("a\n\n b ; c") == Ok(("; c", "a b".to_owned())));
+        assert!(text.parse(" \t;") == Ok((";", String::new())));
+    }
+
+    #[test]
+    fn test_map_parser () {
+        let list = prefix("[", map_parser(take_until("]"), zero_or_more(parse_number)));
+
+        let (buf, v) = list.parse("[1 2 3] rest").unwrap();
+        assert!(v == vec![1.0, 2.0, 3.0] && buf == "] rest");
+
+        // Inner parser may not stop short of the captured chunk
+        assert!(list.parse("[1 2 x] rest").is_err());
+        assert!(list.parse("[1 2 3").is_err());
+    }
+
+    #[test]
+    fn test_literal_map () {
+        #[derive(Debug, PartialEq, Clone, Copy)]
+        enum BinOp { Add, Sub, Mul, Pow, Div }
+        let op = literal_map(&[("+", BinOp::Add), ("-", BinOp::Sub), ("*", BinOp::Mul), ("**", BinOp::Pow), ("/", BinOp::Div)]);
+
+        assert!(op.parse(" - 1") == Ok((" 1", BinOp::Sub)));
+        assert!(op.parse("/") == Ok(("", BinOp::Div)));
+        assert!(op.parse("** 2") == Ok((" 2", BinOp::Pow)));
+
+        let err = op.parse("%").unwrap_err();
+        assert!(err.msg == r#"Literal '["+", "-", "*", "**", "/"]' not found"#);
+        assert!(err.expected_labels() == vec!["'+'", "'-'", "'*'", "'**'", "'/'"]);
+    }
+
+    #[test]
+    fn test_keyword_of () {
+        let kw = keyword_of(&["for", "foreach", "in"]);
+
+        let (buf, s) = kw.parse("FOR x").unwrap();
+        assert!(s == "FOR" && buf == " x");
+
+        // Longest whole-word match wins
+        let (buf, s) = kw.parse("ForEach(x)").unwrap();
+        assert!(s == "ForEach" && buf == "(x)");
+
+        assert!(keyword_of(&["for"]).parse("FOREACH").is_err());
+        assert!(kw.parse("inner").is_err());
+    }
+
+    #[test]
+    fn test_keyword_suggest () {
+        let kw = keyword_suggest(&["function", "let", "return"]);
+        assert!(kw.parse(" let x") == Ok((" x", "let".to_owned())));
+
+        let err = kw.parse("fnuction f").unwrap_err();
+        assert!(err.msg == "unknown keyword 'fnuction', did you mean 'function'?");
+        assert!(kw.parse("banana").unwrap_err().msg == "unknown keyword 'banana'");
+        assert!(kw.parse("lt").unwrap_err().msg.ends_with("did you mean 'let'?"));
+
+        assert!(levenshtein("kitten", "sitting") == 3 && levenshtein("", "ab") == 2);
+    }
+
+    #[test]
+    fn test_must_consume () {
+        let p = must_consume(1, option(parse_literal("x")));
+
+        assert!(p.parse("xy").unwrap() == ("y", Some("x".to_owned())));
+
+        let err = p.parse("y").unwrap_err();
+        assert!(err.msg == "consumed 0 of the required 1 bytes" && err.ptr == "y");
+    }
+
+    #[test]
+    fn test_try_map () {
+        use std::str::FromStr;
+
+        #[derive(Debug, PartialEq)]
+        enum Color { Red, Green }
+        impl FromStr for Color {
+            type Err = String;
+            fn from_str (s: &str) -> Result<Self, String> {
+                match s {
+                    "red"   => Ok(Color::Red),
+                    "green" => Ok(Color::Green),
+                    _       => Err(format!("unknown color '{s}'")),
+                }
+            }
+        }
+
+        let color = BoxedParser::new(parse_identifier).try_map(|s| s.parse::<Color>());
+
+        let (buf, c) = color.parse("green;").unwrap();
+        assert!(c == Color::Green && buf == ";");
+
+        let err = color.parse("blue;").unwrap_err();
+        assert!(err == ParseErr::new("unknown color 'blue'".to_owned(), "blue;"));
+    }
+
+    #[test]
+    fn test_balanced () {
+        let braces = balanced('{', '}');
+
+        let (buf, s) = braces.parse("{a{b}c} rest").unwrap();
+        assert!(s == "a{b}c" && buf == " rest");
+
+        let (buf, s) = braces.parse("{}").unwrap();
+        assert!(s.is_empty() && buf.is_empty());
+
+        assert!(braces.parse("{a{b}").is_err());
+        assert!(braces.parse("a{b}").is_err());
+    }
+
+    #[test]
+    fn test_nested_block_comment () {
+        let comment = nested_block_comment("/*", "*/");
+        assert!(comment.parse(" /* a */ x") == Ok((" x", ())));
+        assert!(comment.parse("/* a /* b */ c */x") == Ok(("x", ())));
+        assert!(comment.parse("/*/**/ /* é */*/ */") == Ok((" */", ())));
+
+        let err = comment.parse("/* a /* b */ c").unwrap_err();
+        assert!(err.msg == "unterminated comment, missing '*/'");
+        assert!(comment.parse("a /* */").is_err());
+    }
+
+    #[test]
+    fn test_parse_rust_int () {
+        assert!(parse_rust_int("1_000").unwrap() == ("", 1000));
+        assert!(parse_rust_int("0xDE_AD").unwrap() == ("", 0xDEAD));
+        assert!(parse_rust_int("0b1010_u8").unwrap() == ("", 10));
+        assert!(parse_rust_int("42u8 + 1").unwrap() == (" + 1", 42));
+
+        assert!(parse_rust_int("_1").is_err());
+        assert!(parse_rust_int("1__0").is_err());
+        assert!(parse_rust_int("0x").is_err());
+        assert!(parse_rust_int("42q").is_err());
+    }
+
+    #[test]
+    fn test_logical_line () {
+        let line = logical_line();
+
+        let (buf, s) = line.parse("a = 1\nb = 2").unwrap();
+        assert!(s == "a = 1" && buf == "b = 2");
+
+        let (buf, s) = line.parse("a = 1 + \\\r\n    2\nb").unwrap();
+        assert!(s == "a = 1 +     2" && buf == "b");
+
+        assert!(line.parse("a\rb") == Ok(("b", "a".to_owned())));
+
+        // A continuation just before the end of input is dropped
+        assert!(line.parse("a \\\n") == Ok(("", "a ".to_owned())));
+        assert!(line.parse("a \\") == Ok(("", "a \\".to_owned())));
+
+        // Final line without a newline
+        let (buf, s) = line.parse("b = 2").unwrap();
+        assert!(s == "b = 2" && buf.is_empty());
+        assert!(line.parse("").is_err());
+
+        let lines = zero_or_more(logical_line()).parse("x\\\ny\nz\n").unwrap().1;
+        assert!(lines == vec!["xy", "z"]);
+    }
+
+    #[test]
+    fn test_line_ending () {
+        assert!(line_ending().parse("\r\nx") == Ok(("x", "\r\n".to_owned())));
+        assert!(line_ending().parse("\rx") == Ok(("x", "\r".to_owned())));
+        assert!(line_ending().parse("\nx") == Ok(("x", "\n".to_owned())));
+
+        assert!(split_line("a\r\nb") == ("a", "\r\n", "b"));
+        assert!(split_line("a\rb\n") == ("a", "\r", "b\n"));
+        assert!(split_line("a") == ("a", "", ""));
+        assert!(line_ending().parse(" \n").is_err());
+    }
+
+    #[test]
+    fn test_key_values () {
+        let kv = key_values("=", line_ending());
+
+        // The trailing line ending is left, as no entry follows it
+        let (buf, map) = kv.parse("a = 1\n  b=two words  \r\nc =\n").unwrap();
+        assert!(map.len() == 3 && buf == "\n");
+        assert!(map["a"] == "1" && map["b"] == "two words" && map["c"].is_empty());
+
+        let err = kv.parse("a = 1\nb = 2\na = 3").unwrap_err();
+        assert!(err.msg == "duplicate key 'a'" && err.ptr == "a = 3");
+
+        // Keys are not restricted by the interpreter's keywords
+        let (buf, map) = kv.parse("true = 1\nif = 2").unwrap();
+        assert!(buf.is_empty() && map["true"] == "1" && map["if"] == "2");
+
+        assert!(kv.parse("  ") == Ok(("", HashMap::new())));
+        assert!(kv.parse("= 1\na = 2").is_err());
+    }
+
+    #[test]
+    fn test_balanced_tolerant () {
+        let braces = balanced_tolerant('{', '}');
+
+        let (buf, (s, closed)) = braces.parse("{a} rest").unwrap();
+        assert!(s == "a" && closed && buf == " rest");
+
+        let (buf, (s, closed)) = braces.parse("{a").unwrap();
+        assert!(s == "a" && !closed && buf.is_empty());
+
+        let (_, (s, closed)) = braces.parse("{a{b}c").unwrap();
+        assert!(s == "a{b}c" && !closed);
+    }
+
+    #[test]
+    fn test_parse_string () {
+        assert!(parse_string(r#""a \"b\"\n" rest"#).unwrap() == (" rest", "a \"b\"\n".to_owned()));
+        assert!(parse_quoted(r"'a\n'", '\'', false).unwrap() == ("", r"a\n".to_owned()));
+
+        assert!(parse_string(r#""abc"#).is_err());
+        assert!(parse_string(r#""\q""#).is_err());
+        assert!(parse_string("abc").is_err());
+    }
+
+    #[test]
+    fn test_quoted () {
+        assert!(quoted().parse("'abc'") == Ok(("", "abc".to_owned())));
+        assert!(quoted().parse(r#" "a'b\"" x"#) == Ok((" x", "a'b\"".to_owned())));
+        assert!(quoted().parse(r#"'it\'s'"#) == Ok(("", "it's".to_owned())));
+
+        let err = quoted().parse(r#"'abc""#).unwrap_err();
+        assert!(err.msg == "unterminated string, missing '''");
+        assert!(quoted().parse("abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_string_cow () {
+        let (rest, s) = parse_string_cow(r#" "plain" x"#).unwrap();
+        assert!(rest == " x" && s == "plain" && matches!(s, Cow::Borrowed(_)));
+
+        let (_, s) = parse_string_cow(r#""a\nb""#).unwrap();
+        assert!(s == "a\nb" && matches!(s, Cow::Owned(_)));
+
+        assert!(parse_string_cow(r#""plain"#).is_err());
+        assert!(parse_string_cow("plain").is_err());
+    }
+
+    #[test]
+    fn test_interpolated () {
+        use Segment::*;
+        let tpl = interpolated(parse_identifier);
+        let (rest, segs) = tpl.parse(r#""hello ${name}, \${x} is ${ count }\n" rest"#).unwrap();
+        assert!(rest == " rest");
+        assert!(segs == vec![Text("hello ".into()), Expr("name".into()), Text(", ${x} is ".into()), Expr("count".into()), Text("\n".into())]);
+
+        // Nested braces belong to the expression
+        let tpl = interpolated(balanced('{', '}'));
+        assert!(tpl.parse(r#""${{a}}""#) == Ok(("", vec![Expr("a".to_owned())])));
+
+        assert!(interpolated(parse_identifier).parse(r#""${a b}""#).is_err());
+        assert!(interpolated(parse_identifier).parse(r#""${a""#).is_err());
+    }
+
+    #[test]
+    fn test_word_literal () {
+        let p = with_origin(bind(|buf: &str| {
+            let found: Vec<bool> = [1, 2, 3].iter().map(|&i| word_literal("in").parse(&buf[i..]).is_ok()).collect();
+            Ok((buf, found))
+        }));
+        assert!(p.parse("x in y").unwrap().1 == vec![true, true, false]);
+        assert!(p.parse("print").unwrap().1 == vec![false, false, false]);
+        assert!(with_origin(word_literal("in")).parse(" in y") == Ok((" y", "in".to_owned())));
+        assert!(with_origin(word_literal("in")).parse("ins").is_err());
+
+        let err = word_literal("in").parse("x in y").unwrap_err();
+        assert!(err.msg == "word_literal used outside of with_origin");
+    }
+
+    #[test]
+    fn test_word () {
+        assert!(word("hello, world").unwrap() == (", world", "hello".to_owned()));
+        assert!(word(", world").is_err());
+
+        // Check for a boundary at every position of the input
+        let input = "hi, x_1";
+        let boundaries = with_origin(raw(|buf: &str| {
+            let v: Vec<bool> = (0..=buf.len())
+                .map(|i| word_boundary().parse(&buf[i..]).is_ok())
+                .collect();
+            Ok((buf, v))
+        }));
+        let (_, v) = boundaries.parse(input).unwrap();
+        assert!(v == vec![true, false, true, false, true, false, false, true]);
+
+        // Without an origin, the start of input can't be told from "foo"[1..]
+        let err = word_boundary().parse(&"foo"[1..]).unwrap_err();
+        assert!(err.msg == "word_boundary used outside of with_origin");
+        assert!(with_origin(word_boundary()).parse("a").is_ok());
+    }
+
+    #[test]
+    fn test_char_class () {
+        let ident = char_class("a-z0-9_");
+        assert!(ident.parse("q1") == Ok(("1", 'q')));
+        assert!(ident.parse("_") == Ok(("", '_')));
+        assert!(ident.parse("Q").is_err());
+        assert!(ident.parse(" q").is_err());
+
+        let unquoted = char_class("^\"\\");
+        assert!(unquoted.parse("é") == Ok(("", 'é')));
+        assert!(unquoted.parse("\"").is_err());
+        assert!(unquoted.parse("\\n").is_err());
+
+        assert!(char_class("+-").parse("-") == Ok(("", '-')));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_char_class_malformed () {
+        char_class("z-a");
+    }
+
+    #[test]
+    fn test_take () {
+        assert!(take(2).parse(" abc").unwrap() == ("bc", " a".to_owned()));
+        assert!(take(4).parse("abc").is_err());
+        assert!(take(1).parse("é").is_err());
+    }
+
+    #[test]
+    fn test_parse_int () {
+        assert!(parse_int("042;").unwrap() == (";", 42));
+        assert!(parse_int("99999999999999999999").is_err());
+        assert!(parse_int("-1").is_err());
+    }
+
+    #[test]
+    fn test_signed () {
+        assert!(signed(parse_number).parse("-3.5") == Ok(("", -3.5)));
+        assert!(signed(parse_number).parse(" +2") == Ok(("", 2.0)));
+        assert!(signed(parse_int).parse("42;") == Ok((";", 42)));
+        assert!(signed(parse_int).parse("- 1").is_err());
+    }
+
+    #[test]
+    fn test_quantity () {
+        #[derive(Debug, PartialEq, Clone, Copy)]
+        enum Unit { Metre, Milli, Second, Gram, Kilo }
+        let q = quantity(&[("m", Unit::Metre), ("ms", Unit::Milli), ("s", Unit::Second), ("g", Unit::Gram), ("kg", Unit::Kilo)]);
+
+        assert!(q.parse("500ms") == Ok(("", (500.0, Unit::Milli))));
+        assert!(q.parse(" 3.5m, 10kg") == Ok((", 10kg", (3.5, Unit::Metre))));
+        assert!(q.parse("10xyz").unwrap_err().msg == "expected unit after 10");
+        assert!(q.parse("10 m").is_err());
+        assert!(q.parse("10mg").is_err());
+    }
+
+    #[test]
+    fn test_parse_f32 () {
+        let (buf, a) = parse_f32("3.25 rest").unwrap();
+        let (_, b) = parse_number("3.25 rest").unwrap();
+        assert!(buf == " rest");
+        assert!(a == 3.25 && b == 3.25);
+
+        // Same input, but f32 rounds differently
+        let (_, a) = parse_f32("0.1").unwrap();
+        let (_, b) = parse_number("0.1").unwrap();
+        assert!(a == 0.1f32 && a as f64 != b);
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_parse_decimal () {
+        let (_, d) = parse_decimal("0.1").unwrap();
+        assert!(d == Decimal { digits: 1, scale: 1 });
+
+        let (buf, d) = parse_decimal("1234.50;").unwrap();
+        assert!(buf == ";" && d == Decimal { digits: 123450, scale: 2 });
+
+        assert!(parse_decimal("1.2.3").is_err());
+        assert!(parse_decimal("1e3").is_err());
+    }
+}
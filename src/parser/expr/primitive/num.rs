@@ -158,5 +158,11 @@ mod tests {
         assert!(expr.test(input2).eval(&mut env).unwrap() == 7.0);
         assert!(expr.test(input3).eval(&mut env).unwrap() == 18.0);
         assert!(expr.test(input4).eval(&mut env).unwrap() == 40.0);
+
+        // Literals take an exponent, as `parse_number` does
+        assert!(expr.test("1e5").eval(&mut env).unwrap() == 100000.0);
+        assert!(expr.test("2.5E-1*4+1").eval(&mut env).unwrap() == 2.0);
+        let (buf, _) = expr.parse("2e+x").unwrap();
+        assert!(buf == "e+x");
     }
 }
@@ -0,0 +1,391 @@
+use crate::parser::{
+    *,
+    core::*,
+};
+
+
+// Reads exactly two hex digits as a byte
+pub fn hex_u8<'a> (buf: &'a str) -> ParseRes<'a, u8> {
+    match buf.get(0..2) {
+        Some(s) if s.chars().all(|c| c.is_ascii_hexdigit()) => 
+            Ok((&buf[2..], u8::from_str_radix(s, 16).unwrap())),
+        _ => par_err(buf, "expected two hex digits")
+    }
+}
+
+
+/// CSS-style color, `#RRGGBB` or the `#RGB` shorthand, as (red, green, blue)
+pub fn parse_hex_color<'a> (buf: &'a str) -> ParseRes<'a, (u8, u8, u8)> {
+    match hex_color(buf)? {
+        (rest, (len, (r, g, b, _))) if len != 8 => Ok((rest, (r, g, b))),
+        _ => par_err(buf, "malformed color, expected #RGB or #RRGGBB")
+    }
+}
+
+
+/// Like `parse_hex_color`, also accepting `#RRGGBBAA`. Alpha defaults to 255.
+pub fn parse_hex_rgba<'a> (buf: &'a str) -> ParseRes<'a, (u8, u8, u8, u8)> {
+    hex_color(buf).map(|(rest, (_, color))| (rest, color))
+}
+
+// Parses any supported color, along with how many digits it was written with
+fn hex_color<'a> (buf: &'a str) -> ParseRes<'a, (usize, (u8, u8, u8, u8))> {
+    let (digits, _) = parse_literal("#").parse(buf)?;
+    let len = digits.chars().take_while(|c| c.is_ascii_hexdigit()).count();
+
+    let color = match len {
+        3 => {
+            // Each shorthand digit is doubled, eg. F -> FF
+            let v: Vec<u8> = digits[..3].chars()
+                .map(|c| c.to_digit(16).unwrap() as u8 * 0x11)
+                .collect();
+            (v[0], v[1], v[2], 255)
+        },
+        6 | 8 => {
+            let (s, r) = hex_u8(digits)?;
+            let (s, g) = hex_u8(s)?;
+            let (s, b) = hex_u8(s)?;
+            let a = if len == 8 { hex_u8(s)?.1 } else { 255 };
+            (r, g, b, a)
+        },
+        _ => return par_err(buf, "malformed color, expected #RGB, #RRGGBB or #RRGGBBAA")
+    };
+    Ok((&digits[len..], (len, color)))
+}
+
+
+/// Splits the buffer into shell-style words separated by whitespace. Quoted
+/// groups may contain whitespace; escapes are decoded only in double quotes.
+pub fn shell_words () -> impl Parser<Vec<String>> {
+    raw(|buf: &str| {
+        let mut words = vec![];
+        let mut s = buf.trim_start();
+        while !s.is_empty() {
+            let mut word = String::new();
+            while let Some(c) = s.chars().next() {
+                if c.is_whitespace() { break }
+
+                let (rest, part) = match c {
+                    '"'  => parse_quoted(s, '"', true)?,
+                    '\'' => parse_quoted(s, '\'', false)?,
+                    _    => {
+                        let end = s.find(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+                            .unwrap_or(s.len());
+                        (&s[end..], s[..end].to_owned())
+                    }
+                };
+                word += &part;
+                s = rest;
+            }
+            words.push(word);
+            s = s.trim_start();
+        }
+        Ok((s, words))
+    })
+}
+
+
+/// Heredoc, `<open><TAG>` then lines of body up to a line of just `TAG`.
+/// Returns the body, including its final line ending.
+pub fn heredoc<'a> (open: &str) -> BoxedParser<'a, String> {
+    BoxedParser::new(prefix(open, parse_identifier))
+        .and_then(|tag| raw(move |buf: &str| {
+            let (mut rest, _) = line_ending().parse(buf)?;
+            let body = rest;
+            loop {
+                let (line, _, next) = split_line(rest);
+                if line == tag {
+                    let len = body.len() - rest.len();
+                    return Ok((&rest[line.len()..], body[..len].to_owned()));
+                }
+                if next.is_empty() {
+                    return par_err_s(body, format!("unterminated heredoc, missing '{}'", tag));
+                }
+                rest = next;
+            }
+        }))
+}
+
+
+/// Lines up to the first starting with `terminator`, which is consumed too,
+/// with the indentation common to all non-blank lines removed. Each line is
+/// returned with a "\n"; blank lines are kept, but emptied.
+pub fn dedented_block<P> (terminator: P) -> impl Parser<String>
+where
+    P: Parser<()>
+{
+    raw(move |buf: &str| {
+        let mut lines = vec![];
+        let mut rest = buf;
+        let rest = loop {
+            if let Ok((rest, _)) = terminator.parse(rest) {
+                break rest;
+            }
+            if rest.is_empty() {
+                return par_err(buf, "unterminated block");
+            }
+            let (line, _, next) = split_line(rest);
+            lines.push(line);
+            rest = next;
+        };
+
+        let indent_of = |l: &str| l.len() - l.trim_start_matches([' ', '\t']).len();
+        let common = lines.iter()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| indent_of(l))
+            .min()
+            .unwrap_or(0);
+
+        let mut out = String::new();
+        for line in lines {
+            if !line.trim().is_empty() {
+                out += &line[common..];
+            }
+            out.push('\n');
+        }
+        Ok((rest, out))
+    })
+}
+
+// Netstring, `<len>:<payload>,` where the payload is exactly `len` bytes
+pub fn netstring<'a> (buf: &'a str) -> ParseRes<'a, String> {
+    let (rest, len) = suffix(":", parse_int).parse(buf)?;
+    let (rest, payload) = take(len as usize).parse(rest)?;
+    match rest.strip_prefix(',') {
+        Some(rest) => Ok((rest, payload)),
+        None       => par_err_s(rest, format!("netstring length {} does not match payload", len))
+    }
+}
+
+
+/// Text up to the first char `stop` accepts, or the end, with `%XX` escapes
+/// decoded, and `+` as a space if `plus_as_space` is set, as in query strings.
+/// The decoded bytes must be UTF-8.
+pub fn percent_decoded<S> (stop: S, plus_as_space: bool) -> impl Parser<String>
+where
+    S: Fn(char) -> bool
+{
+    raw(move |buf: &str| {
+        let end = buf.find(&stop).unwrap_or(buf.len());
+        let mut bytes = vec![];
+        let mut rest = &buf[..end];
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '%' => match hex_u8(&rest[1..]) {
+                    Ok((next, b)) => { bytes.push(b); rest = next },
+                    Err(_) => return par_err_s(rest, format!("malformed escape '{}'", rest.get(..3).unwrap_or(rest))),
+                },
+                '+' if plus_as_space => { bytes.push(b' '); rest = &rest[1..] },
+                c => {
+                    bytes.extend_from_slice(&rest.as_bytes()[..c.len_utf8()]);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        match String::from_utf8(bytes) {
+            Ok(s)  => Ok((&buf[end..], s)),
+            Err(_) => par_err(buf, "percent escapes do not decode to UTF-8"),
+        }
+    })
+}
+
+/// Symbolic mode such as `rwxr-x---`, as its permission bits, eg. `0o750`.
+/// Each of the 9 positions is its flag, or `-` if unset.
+pub fn posix_mode () -> impl Parser<u16> {
+    bind(|buf: &str| {
+        let mut mode = 0;
+        let mut chars = buf.char_indices();
+        for (i, flag) in "rwxrwxrwx".chars().enumerate() {
+            let bit = 1 << (8 - i);
+            match chars.next() {
+                Some((_, c)) if c == flag => mode |= bit,
+                Some((_, '-')) => (),
+                Some((at, c)) => return par_err_s(&buf[at..], format!("expected '{}' or '-' in mode, found '{}'", flag, c)),
+                None => return par_err_s(buf, format!("mode has {} of 9 flags", i)),
+            }
+        }
+        Ok((&buf[9..], mode))
+    })
+}
+
+// Whether the digits of `s` pass the Luhn check, as credit card numbers do.
+// Spaces are ignored; any other non-digit fails.
+pub fn luhn (s: &str) -> bool {
+    let digits: Option<Vec<u32>> = s.chars().filter(|c| *c != ' ').map(|c| c.to_digit(10)).collect();
+    match digits {
+        Some(d) if d.len() > 1 => d.iter().rev().enumerate()
+            .map(|(i, &d)| if i % 2 == 1 { if d * 2 > 9 { d * 2 - 9 } else { d * 2 } } else { d })
+            .sum::<u32>() % 10 == 0,
+        _ => false
+    }
+}
+
+/// Amount of money such as `$1,234.56`, in the smallest unit, eg. cents for
+/// `decimals = 2`. The `currency` sign is optional. Digits may be grouped in
+/// threes by commas; fractional digits, if given, must number `decimals`.
+pub fn parse_money (currency: Option<char>, decimals: u32) -> impl Parser<i64> {
+    bind(move |buf: &str| {
+        let mut rest = match currency {
+            Some(c) => buf.strip_prefix(c).unwrap_or(buf),
+            None    => buf,
+        };
+
+        let start = rest;
+        let mut digits = String::new();
+        let (_, first) = count_while(|c| c.is_ascii_digit()).parse(rest)?;
+        if first.0 == 0 {
+            return par_err(rest, "expected digit");
+        }
+        digits += &first.1;
+        rest = &rest[first.1.len()..];
+        while let Some(group) = rest.strip_prefix(',') {
+            match group.get(..3) {
+                Some(g) if first.0 <= 3 && g.bytes().all(|b| b.is_ascii_digit()) => digits += g,
+                _ => return par_err(start, "malformed digit grouping"),
+            }
+            rest = &group[3..];
+        }
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return par_err(start, "malformed digit grouping");
+        }
+
+        if let Some(frac) = rest.strip_prefix('.') {
+            let (_, (n, f)) = count_while(|c| c.is_ascii_digit()).parse(frac)?;
+            if n != decimals as usize {
+                return par_err_s(frac, format!("expected {} fractional digits, found {}", decimals, n));
+            }
+            digits += &f;
+            rest = &frac[n..];
+        } else {
+            digits += &"0".repeat(decimals as usize);
+        }
+
+        match digits.parse::<i64>() {
+            Ok(v)  => Ok((rest, v)),
+            Err(_) => par_err_s(start, format!("amount out of range for {} decimals", decimals)),
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_color () {
+        assert!(parse_hex_color("#FF8800;").unwrap() == (";", (0xFF, 0x88, 0x00)));
+        assert!(parse_hex_color("#F80").unwrap() == ("", (0xFF, 0x88, 0x00)));
+        assert!(parse_hex_rgba("#FF880080").unwrap() == ("", (0xFF, 0x88, 0x00, 0x80)));
+        assert!(parse_hex_rgba("#F80").unwrap() == ("", (0xFF, 0x88, 0x00, 0xFF)));
+
+        // 3 digits is the shorthand, so anything between 3 and 6 is malformed
+        assert!(parse_hex_color("#FF88").is_err());
+        assert!(parse_hex_color("#FF880080").is_err());
+        assert!(parse_hex_color("FF8800").is_err());
+        assert!(parse_hex_color("").is_err());
+        assert!(hex_u8("F").is_err());
+    }
+
+    #[test]
+    fn test_shell_words () {
+        let (buf, words) = shell_words().parse(r#"foo "bar baz" qux"#).unwrap();
+        assert!(words == vec!["foo", "bar baz", "qux"] && buf.is_empty());
+
+        let (_, words) = shell_words().parse("\t'a \\n'  \"b\\\"c\"d ''\n").unwrap();
+        assert!(words == vec!["a \\n", "b\"cd", ""]);
+
+        assert!(shell_words().parse(r#"foo "bar"#).is_err());
+    }
+
+    #[test]
+    fn test_heredoc () {
+        let doc = heredoc("<<");
+
+        assert!(doc.test("<<END\nbody line\nEND") == "body line\n");
+
+        let (buf, body) = doc.parse("<<EOF\n  a\nEOFX\n\nEOF\nrest").unwrap();
+        assert!(body == "  a\nEOFX\n\n" && buf == "\nrest");
+
+        // Any line ending that `line_ending` takes
+        assert!(doc.parse("<<END\rbody\r\nEND\rx") == Ok(("\rx", "body\r\n".to_owned())));
+
+        assert!(doc.parse("<<END\nbody\n").is_err());
+        assert!(doc.parse("<<END body\nEND").is_err());
+    }
+
+    #[test]
+    fn test_dedented_block () {
+        let block = dedented_block(skip(raw_literal("END")));
+        let (rest, text) = block.parse("    def f():\n\n        return 1\n      # done\nEND rest").unwrap();
+        assert!(text == "def f():\n\n    return 1\n  # done\n" && rest == " rest");
+
+        assert!(block.parse("END") == Ok(("", String::new())));
+        assert!(block.parse("  a\r  b\r\nEND") == Ok(("", "a\nb\n".to_owned())));
+        assert!(block.parse("  a\n  b\n").is_err());
+    }
+
+    #[test]
+    fn test_netstring () {
+        assert!(netstring("5:hello,").unwrap() == ("", "hello".to_owned()));
+        assert!(netstring("0:,5:a, b,").unwrap() == ("5:a, b,", "".to_owned()));
+
+        assert!(netstring("5:hi,").is_err());
+        assert!(netstring("2:hello,").is_err());
+        assert!(netstring("5:hello").is_err());
+    }
+
+    #[test]
+    fn test_parse_money () {
+        let usd = parse_money(Some('$'), 2);
+        assert!(usd.parse("$1,234.56") == Ok(("", 123456)));
+        assert!(usd.parse(" 7 x") == Ok((" x", 700)));
+        assert!(usd.parse("$1234567.00;") == Ok((";", 123456700)));
+
+        let err = usd.parse("$1.5").unwrap_err();
+        assert!(err.msg == "expected 2 fractional digits, found 1");
+        assert!(usd.parse("$1,23.00").is_err());
+        assert!(usd.parse("$1234,567").is_err());
+        assert!(usd.parse("$99,999,999,999,999,999").is_err());
+        assert!(parse_money(None, 0).parse("12.5").is_err());
+
+        // Non-ASCII after a comma is malformed, not a bad slice
+        assert!(usd.parse("$1,éé").unwrap_err().msg == "malformed digit grouping");
+        assert!(usd.parse("$1,2é").is_err());
+    }
+
+    #[test]
+    fn test_percent_decoded () {
+        let path = percent_decoded(|c| c == '?', false);
+        assert!(path.parse("hello%20world?q") == Ok(("?q", "hello world".to_owned())));
+        assert!(path.parse("a+b%2Bc%C3%A9") == Ok(("", "a+b+cé".to_owned())));
+
+        let query = percent_decoded(|c| c == '&', true);
+        assert!(query.parse("a+b%26&c") == Ok(("&c", "a b&".to_owned())));
+
+        assert!(path.parse("%ZZ").unwrap_err().msg == "malformed escape '%ZZ'");
+        assert!(path.parse("ab%2").is_err());
+        assert!(path.parse("%C3").is_err());
+    }
+
+    #[test]
+    fn test_posix_mode () {
+        assert!(posix_mode().parse("rwxr-xr-- a") == Ok((" a", 0o754)));
+        assert!(posix_mode().parse("---------") == Ok(("", 0)));
+        assert!(posix_mode().parse("rw-rw-rw-") == Ok(("", 0o666)));
+
+        assert!(posix_mode().parse("rwxr-xr-").unwrap_err().msg == "mode has 8 of 9 flags");
+        let err = posix_mode().parse("rwxw-xr--").unwrap_err();
+        assert!(err.msg == "expected 'r' or '-' in mode, found 'w'" && err.ptr == "w-xr--");
+    }
+
+    #[test]
+    fn test_checksummed () {
+        let card = checksummed(parse_tok_with_rule(|c| c.is_ascii_digit()), |s: &String| luhn(s));
+        assert!(card.parse("4539578763621486 x") == Ok((" x", "4539578763621486".to_owned())));
+
+        let err = card.parse(" 4539578763621487").unwrap_err();
+        assert!(err.msg == "checksum failed" && err.ptr == "4539578763621487");
+        assert!(luhn("79927398713") && luhn("7992 7398 713") && !luhn("7992739871x") && !luhn("0"));
+    }
+}
@@ -7,31 +7,39 @@ pub fn operation<'a> (buf: &'a str) -> ParseRes<'a, Box<dyn Operation>> {
     // If conditional
     map(block::block, box_operation).parse(buf)
     .or_else( 
-        |_| _loop::_for() 
+        |e| unless_fatal(e, || _loop::_for() 
             .map(box_operation)
-            .parse(buf)
+            .parse(buf))
     )
     .or_else(
-        |_| _loop::_while()
+        |e| unless_fatal(e, || _loop::_while()
             .map(box_operation)
-            .parse(buf)
+            .parse(buf))
     )
     .or_else(
-        |_| map(conditional::conditional_if, box_operation)
-            .parse(buf)
+        |e| unless_fatal(e, || map(conditional::conditional_if, box_operation)
+            .parse(buf))
     )
     .or_else(
-        |_| print::print()
-            .parse(buf)
+        |e| unless_fatal(e, || print::print()
+            .parse(buf))
     )
     .or_else(
-        |_| map(declare::declaration, box_operation).parse(buf)
+        |e| unless_fatal(e, || map(declare::declaration, box_operation).parse(buf))
     )
     .or_else(
-        |_| map(assign::assignment(), box_operation).parse(buf) 
+        |e| unless_fatal(e, || map(assign::assignment(), box_operation).parse(buf))
     )
 }
 
+// Tries the next statement form, unless the last one committed and failed
+fn unless_fatal<'a, T, F> (e: ParseErr<'a>, next: F) -> ParseRes<'a, T>
+where
+    F: FnOnce() -> ParseRes<'a, T>
+{
+    if e.is_fatal() { Err(e) } else { next() }
+}
+
 fn box_operation<T> (o: T) -> Box<dyn Operation>
 where
     T: Operation + 'static
@@ -0,0 +1,113 @@
+use crate::parser::*;
+
+
+/// Parser threading a user-defined state `S` through the parse, for context
+/// sensitive grammars, eg. a table of declared names. Closures taking the
+/// state are stateful parsers; plain parsers are brought in with `lift`.
+pub trait StatefulParser<S, T> {
+    fn parse_with<'a> (&self, s: &'a str, state: &mut S) -> ParseRes<'a, T>;
+}
+impl<F, S, T> StatefulParser<S, T> for F
+where
+    F: for<'a> Fn (&'a str, &mut S) -> ParseRes<'a, T>
+{
+    fn parse_with<'a> (&self, input: &'a str, state: &mut S) -> ParseRes<'a, T> {
+        self(skip_whitespace(input), state)
+    }
+}
+
+/// Pins a closure to the `StatefulParser` signature, like `core::bind`
+pub fn stateful<F, S, T> (f: F) -> F
+where
+    F: for<'a> Fn(&'a str, &mut S) -> ParseRes<'a, T>,
+{
+    f
+}
+
+pub struct Lift<P> (P);
+impl<P, S, T> StatefulParser<S, T> for Lift<P>
+where
+    P: Parser<T>
+{
+    fn parse_with<'a> (&self, input: &'a str, _: &mut S) -> ParseRes<'a, T> {
+        self.0.parse(input)
+    }
+}
+
+// Runs a plain parser as a stateful one, leaving the state alone
+pub fn lift<T, P> (p: P) -> Lift<P>
+where
+    P: Parser<T>
+{
+    Lift(p)
+}
+
+// Reads the state through `f`, consuming nothing
+pub fn get_state<S, T, F> (f: F) -> impl StatefulParser<S, T>
+where
+    F: Fn(&S) -> T
+{
+    stateful(move |buf, state: &mut S| Ok((buf, f(state))))
+}
+
+// Updates the state through `f`, consuming nothing
+pub fn set_state<S, F> (f: F) -> impl StatefulParser<S, ()>
+where
+    F: Fn(&mut S)
+{
+    stateful(move |buf, state: &mut S| {
+        f(state);
+        Ok((buf, ()))
+    })
+}
+
+/// Runs `p` from a fresh copy of `init`, returning the final state with the
+/// result. This turns a stateful parser back into a plain one.
+pub fn with_state<S, T, P> (init: S, p: P) -> impl Parser<(T, S)>
+where
+    S: Clone,
+    P: StatefulParser<S, T>
+{
+    bind(move |buf| {
+        let mut state = init.clone();
+        let (buf, o) = p.parse_with(buf, &mut state)?;
+        Ok((buf, (o, state)))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+
+    #[test]
+    fn test_with_state () {
+        // `let <name> = <name or number>`, where names must be declared first
+        let binding = lift(and(prefix("let", parse_identifier), prefix("=", word)));
+        let decl = stateful(move |buf, vars: &mut HashSet<String>| {
+            let (rest, (name, value)) = binding.parse_with(buf, vars)?;
+            let is_num = value.starts_with(|c: char| c.is_ascii_digit());
+            let (rest, known) = get_state(|vars: &HashSet<String>| is_num || vars.contains(&value)).parse_with(rest, vars)?;
+            if !known {
+                return par_err_s(buf, format!("undeclared variable '{}'", value));
+            }
+            let (rest, _) = set_state(|vars: &mut HashSet<String>| { vars.insert(name.clone()); }).parse_with(rest, vars)?;
+            Ok((rest, ()))
+        });
+        let program = stateful(move |buf, vars: &mut HashSet<String>| {
+            let (mut buf, _) = decl.parse_with(buf, vars)?;
+            while let Some(rest) = buf.trim_start().strip_prefix(';') {
+                buf = decl.parse_with(rest, vars)?.0;
+            }
+            Ok((buf, ()))
+        });
+        let p = with_state(HashSet::new(), program);
+
+        let (_, (_, vars)) = p.parse("let x = 1; let y = x").unwrap();
+        assert!(vars.len() == 2 && vars.contains("y"));
+
+        let err = p.parse("let x = 1; let y = z").unwrap_err();
+        assert!(err.msg == "undeclared variable 'z'");
+    }
+}